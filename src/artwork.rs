@@ -0,0 +1,427 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    io::{self, Cursor, Write},
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{DynamicImage, ImageFormat, RgbaImage, imageops::FilterType};
+
+use crate::{
+    database,
+    providers::{self, Update},
+};
+
+pub const ARTWORK_ARG: &str = "artwork";
+const PROTOCOL_ENV: &str = "ARTWORK_PROTOCOL";
+
+// Size of the cover in terminal cells, cells being about twice as high as wide
+const WIDTH_CELLS: u32 = 20;
+const HEIGHT_CELLS: u32 = 10;
+const CELL_WIDTH_PX: u32 = 10;
+const CELL_HEIGHT_PX: u32 = 20;
+
+// https://sw.kovidgoyal.net/kitty/graphics-protocol/#remote-client
+const KITTY_CHUNK_SIZE: usize = 4096;
+const KITTY_MAX_PX: u32 = 400;
+
+// A hanging image host shouldn't hold back whoever is waiting on the cover
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Iterm,
+    /// Two pixels per cell using `▀` with different colors, works almost everywhere
+    HalfBlocks,
+}
+
+impl Protocol {
+    /// `ARTWORK_PROTOCOL` if set, guessed from the terminal otherwise.
+    pub fn from_env() -> Self {
+        Protocol::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        match var(PROTOCOL_ENV).map(|p| p.to_lowercase()).as_deref() {
+            Some("kitty") => Protocol::Kitty,
+            Some("sixel") => Protocol::Sixel,
+            Some("iterm") | Some("iterm2") => Protocol::Iterm,
+            Some("halfblocks") => Protocol::HalfBlocks,
+            Some(protocol) => {
+                log::warn!("Unknown {} `{}`, guessing it", PROTOCOL_ENV, protocol);
+                Protocol::detect(var)
+            }
+            None => Protocol::detect(var),
+        }
+    }
+
+    fn detect(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let term_program = var("TERM_PROGRAM").unwrap_or_default();
+
+        if var("KITTY_WINDOW_ID").is_some() || term == "xterm-kitty" || term_program == "ghostty" {
+            Protocol::Kitty
+        } else if term_program == "iTerm.app" || term_program == "WezTerm" {
+            Protocol::Iterm
+        } else if term.starts_with("foot") || term.starts_with("mlterm") || term.contains("sixel") {
+            Protocol::Sixel
+        } else {
+            Protocol::HalfBlocks
+        }
+    }
+}
+
+// MPRIS players use `file://` URLs with percent-encoded paths
+fn decode_file_url(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Downloads the artwork, or reads it from the `database` cache.
+pub async fn fetch(url: &str) -> Result<Vec<u8>, providers::Error> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok(std::fs::read(decode_file_url(path))?);
+    }
+    if let Some(content) = database::artwork::get_artwork(url) {
+        log::debug!("Using cached artwork for {}", url);
+        return Ok(content);
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    let content = response.bytes().await?.to_vec();
+    database::artwork::set_artwork(url, &content);
+    Ok(content)
+}
+
+fn push_run(output: &mut String, sixel: char, count: usize) {
+    match count {
+        0 => {}
+        1..=3 => (0..count).for_each(|_| output.push(sixel)),
+        _ => output.push_str(&format!("!{}{}", count, sixel)),
+    }
+}
+
+// Colors are rounded to a 6x6x6 cube, plenty for a thumbnail
+fn palette_index(pixel: &image::Rgba<u8>) -> Option<usize> {
+    if pixel[3] < 128 {
+        return None;
+    }
+    let level = |value: u8| (value as usize * 5 + 127) / 255;
+    Some(level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]))
+}
+
+// https://vt100.net/docs/vt3xx-gp/chapter14.html
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut output = format!("\x1bPq\"1;1;{};{}", width, height);
+    for index in 0..216 {
+        let (r, g, b) = (index / 36, (index / 6) % 6, index % 6);
+        output.push_str(&format!("#{};2;{};{};{}", index, r * 20, g * 20, b * 20));
+    }
+
+    let colors: Vec<Option<usize>> = image.pixels().map(palette_index).collect();
+    let color_at = |x: u32, y: u32| colors[(y * width + x) as usize];
+
+    for top in (0..height).step_by(6) {
+        let rows = top..(top + 6).min(height);
+        let band_colors: BTreeSet<usize> = rows
+            .clone()
+            .flat_map(|y| (0..width).filter_map(move |x| color_at(x, y)))
+            .collect();
+
+        for (i, color) in band_colors.iter().enumerate() {
+            if i > 0 {
+                // Back to the start of the band for the next color
+                output.push('$');
+            }
+            output.push_str(&format!("#{}", color));
+
+            let mut run = ('?', 0);
+            for x in 0..width {
+                let bits = rows
+                    .clone()
+                    .filter(|y| color_at(x, *y) == Some(*color))
+                    .fold(0, |bits, y| bits | 1 << (y - top));
+                let sixel = char::from(63 + bits as u8);
+                if sixel == run.0 {
+                    run.1 += 1;
+                } else {
+                    push_run(&mut output, run.0, run.1);
+                    run = (sixel, 1);
+                }
+            }
+            push_run(&mut output, run.0, run.1);
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\");
+    output
+}
+
+fn half_blocks(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut output = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = image.get_pixel(x, y);
+            output.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+            if y + 1 < height {
+                let bottom = image.get_pixel(x, y + 1);
+                output.push_str(&format!(
+                    "\x1b[48;2;{};{};{}m",
+                    bottom[0], bottom[1], bottom[2]
+                ));
+            }
+            output.push('▀');
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output
+}
+
+fn kitty(image: &DynamicImage) -> Result<String, image::ImageError> {
+    // Kitty scales it to the cells by itself, only shrink the big ones
+    let image = match image.width().max(image.height()) > KITTY_MAX_PX {
+        true => image.thumbnail(KITTY_MAX_PX, KITTY_MAX_PX),
+        false => image.clone(),
+    };
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let encoded = STANDARD.encode(png);
+
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut output = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        let chunk = String::from_utf8_lossy(chunk);
+        match i {
+            0 => output.push_str(&format!(
+                "\x1b_Gf=100,a=T,c={},r={},m={};{}\x1b\\",
+                WIDTH_CELLS, HEIGHT_CELLS, more, chunk
+            )),
+            _ => output.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk)),
+        }
+    }
+    output.push('\n');
+    Ok(output)
+}
+
+// https://iterm2.com/documentation-images.html
+fn iterm(content: &[u8]) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07\n",
+        content.len(),
+        WIDTH_CELLS,
+        HEIGHT_CELLS,
+        STANDARD.encode(content)
+    )
+}
+
+/// Escape sequences drawing `content` with `protocol`, ending on a new line.
+pub fn render(protocol: Protocol, content: &[u8]) -> Result<String, image::ImageError> {
+    // iTerm2 decodes the image itself
+    if protocol == Protocol::Iterm {
+        return Ok(iterm(content));
+    }
+
+    let image = image::load_from_memory(content)?;
+    Ok(match protocol {
+        Protocol::Kitty => kitty(&image)?,
+        Protocol::Sixel => {
+            sixel(
+                &image
+                    .resize(
+                        WIDTH_CELLS * CELL_WIDTH_PX,
+                        HEIGHT_CELLS * CELL_HEIGHT_PX,
+                        FilterType::Triangle,
+                    )
+                    .to_rgba8(),
+            ) + "\n"
+        }
+        _ => half_blocks(
+            &image
+                .resize(WIDTH_CELLS, HEIGHT_CELLS * 2, FilterType::Triangle)
+                .to_rgba8(),
+        ),
+    })
+}
+
+/// Prints the cover above the text output whenever it changes.
+pub struct Artwork {
+    protocol: Protocol,
+    previous_url: Option<String>,
+}
+
+impl Artwork {
+    pub fn new() -> Self {
+        let protocol = Protocol::from_env();
+        log::debug!("Rendering artwork with {:?}", protocol);
+        Artwork {
+            protocol,
+            previous_url: None,
+        }
+    }
+
+    pub async fn show(&mut self, update: &Update) {
+        if update.event.is_none() {
+            return;
+        }
+        let url = match update
+            .song
+            .as_ref()
+            .and_then(|song| song.artwork_url.clone())
+        {
+            Some(url) if Some(&url) != self.previous_url.as_ref() => url,
+            _ => return,
+        };
+
+        let content = match fetch(&url).await {
+            Ok(content) => content,
+            Err(err) => {
+                log::warn!("Couldn't fetch artwork {}: {}", url, err);
+                return;
+            }
+        };
+        match render(self.protocol, &content) {
+            Ok(rendered) => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(rendered.as_bytes());
+                let _ = stdout.flush();
+            }
+            Err(err) => log::warn!("Couldn't decode artwork {}: {}", url, err),
+        }
+        self.previous_url = Some(url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol(vars: &[(&str, &str)]) -> Protocol {
+        Protocol::from_vars(|name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn protocol_from_the_setting() {
+        assert_eq!(protocol(&[(PROTOCOL_ENV, "Sixel")]), Protocol::Sixel);
+        assert_eq!(protocol(&[(PROTOCOL_ENV, "iterm2")]), Protocol::Iterm);
+        // The setting wins over the terminal
+        assert_eq!(
+            protocol(&[(PROTOCOL_ENV, "halfblocks"), ("TERM", "xterm-kitty")]),
+            Protocol::HalfBlocks
+        );
+        assert_eq!(
+            protocol(&[(PROTOCOL_ENV, "braille"), ("TERM", "foot")]),
+            Protocol::Sixel
+        );
+    }
+
+    #[test]
+    fn protocol_from_the_terminal() {
+        assert_eq!(protocol(&[("KITTY_WINDOW_ID", "1")]), Protocol::Kitty);
+        assert_eq!(protocol(&[("TERM", "xterm-kitty")]), Protocol::Kitty);
+        assert_eq!(protocol(&[("TERM_PROGRAM", "ghostty")]), Protocol::Kitty);
+        assert_eq!(protocol(&[("TERM_PROGRAM", "WezTerm")]), Protocol::Iterm);
+        assert_eq!(protocol(&[("TERM", "foot-extra")]), Protocol::Sixel);
+        assert_eq!(protocol(&[("TERM", "xterm-sixel")]), Protocol::Sixel);
+        assert_eq!(
+            protocol(&[("TERM", "xterm-256color")]),
+            Protocol::HalfBlocks
+        );
+        assert_eq!(protocol(&[]), Protocol::HalfBlocks);
+    }
+
+    #[test]
+    fn decodes_file_urls() {
+        assert_eq!(
+            decode_file_url("/home/me/My%20Music/caf%C3%A9.jpg"),
+            "/home/me/My Music/café.jpg"
+        );
+        // Invalid or truncated escapes are kept as is
+        assert_eq!(decode_file_url("/100%/%zz/%4"), "/100%/%zz/%4");
+    }
+
+    #[test]
+    fn palette_rounds_to_the_cube() {
+        assert_eq!(palette_index(&image::Rgba([0, 0, 0, 255])), Some(0));
+        assert_eq!(palette_index(&image::Rgba([255, 255, 255, 255])), Some(215));
+        assert_eq!(palette_index(&image::Rgba([255, 0, 0, 128])), Some(180));
+        assert_eq!(palette_index(&image::Rgba([30, 100, 230, 255])), Some(53));
+        assert_eq!(palette_index(&image::Rgba([255, 0, 0, 127])), None);
+    }
+
+    #[test]
+    fn run_lengths() {
+        let mut output = String::new();
+        push_run(&mut output, '~', 0);
+        push_run(&mut output, '@', 3);
+        push_run(&mut output, '?', 4);
+        assert_eq!(output, "@@@!4?");
+    }
+
+    #[test]
+    fn sixel_bands() {
+        let red = image::Rgba([255, 0, 0, 255]);
+        let blue = image::Rgba([0, 0, 255, 255]);
+        let clear = image::Rgba([0, 0, 0, 0]);
+        // Red on the first row, blue below on the left, nothing on the right
+        let image = RgbaImage::from_fn(6, 7, |x, y| match (x, y) {
+            (_, 0) => red,
+            (0..=1, _) => blue,
+            _ => clear,
+        });
+
+        let output = sixel(&image);
+        assert!(output.starts_with("\x1bPq\"1;1;6;7#0;2;0;0;0#1;2;0;0;20"));
+        let bands = &output[output.find("#5;2;0;0;100").unwrap() + 12..];
+        let bands = &bands[bands.find("#215;2;100;100;100").unwrap() + 18..];
+        // Blue covers the 5 lower rows of the first band then the single row
+        // of the second one, red only the top row
+        assert_eq!(bands, "#5}}!4?$#180!6@-#5@@!4?-\x1b\\");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fetch_gives_up_on_hanging_hosts() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/cover.jpg", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let start = tokio::time::Instant::now();
+        assert!(fetch(&url).await.is_err());
+        assert_eq!(start.elapsed().as_secs(), REQUEST_TIMEOUT_SECS);
+    }
+}
@@ -1 +1,3 @@
 pub mod connect;
+pub mod serve;
+pub mod tui;
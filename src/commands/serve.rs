@@ -0,0 +1,461 @@
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer, Responder, get, http::StatusCode, middleware, web,
+};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    artwork,
+    providers::{self, MusicProvider, State, Update},
+    sinks::Sinks,
+    template::Templates,
+    utils::get_server_env,
+};
+
+pub const IP_ENV: &str = "SERVER_IP";
+pub const PORT_ENV: &str = "SERVER_PORT";
+pub const DEFAULT_IP: &str = "0.0.0.0";
+pub const DEFAULT_PORT: u16 = 9762;
+
+const WIDGET: &str = include_str!("widget.html");
+
+// Slow clients skip the updates they missed past that
+const EVENTS_CAPACITY: usize = 16;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Latest update from the provider, `None` until the first one arrives.
+#[derive(Clone)]
+struct NowPlaying {
+    update: Arc<Mutex<Option<Update>>>,
+    /// Only updates with an event, pushed to `/events` and `/ws`
+    sender: broadcast::Sender<Update>,
+}
+
+impl NowPlaying {
+    fn new() -> Self {
+        NowPlaying {
+            update: Arc::new(Mutex::new(None)),
+            sender: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
+
+    fn set(&self, update: Update) {
+        let mut latest = self.update.lock().unwrap();
+        if update.event.is_some() {
+            // No receivers is fine, nobody is listening yet
+            let _ = self.sender.send(update.clone());
+        }
+        *latest = Some(update);
+    }
+
+    fn get(&self) -> Option<Update> {
+        self.update.lock().unwrap().clone()
+    }
+
+    /// Latest update and every following one, without missing any in between.
+    fn subscribe(&self) -> (Option<Update>, broadcast::Receiver<Update>) {
+        let latest = self.update.lock().unwrap();
+        (latest.clone(), self.sender.subscribe())
+    }
+}
+
+fn to_json(update: &Update) -> String {
+    serde_json::to_string(update).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    platform: &'static str,
+    /// Seconds since UNIX epoch of the latest update
+    last_update: Option<u64>,
+    state: Option<State>,
+}
+
+fn not_ready() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({
+        "error": "No update from the provider yet"
+    }))
+}
+
+#[get("/now-playing")]
+async fn now_playing(latest: web::Data<NowPlaying>) -> impl Responder {
+    match latest.get() {
+        Some(update) => HttpResponse::Ok().json(update),
+        None => not_ready(),
+    }
+}
+
+#[get("/now-playing.txt")]
+async fn now_playing_text(
+    latest: web::Data<NowPlaying>,
+    templates: web::Data<Templates>,
+) -> impl Responder {
+    let (status, text) = match latest.get() {
+        Some(update) => match update.error {
+            Some(err) => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+            None => (
+                StatusCode::OK,
+                templates.render(update.song.as_ref(), update.platform),
+            ),
+        },
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No update from the provider yet".to_string(),
+        ),
+    };
+    HttpResponse::build(status)
+        .content_type("text/plain; charset=utf-8")
+        .body(text)
+}
+
+#[get("/health")]
+async fn health(
+    latest: web::Data<NowPlaying>,
+    platform: web::Data<&'static str>,
+) -> impl Responder {
+    let update = latest.get();
+    let health = Health {
+        status: match &update {
+            Some(update) if update.state != State::Error => "ok",
+            _ => "unavailable",
+        },
+        platform: platform.get_ref(),
+        last_update: update.as_ref().map(|update| update.timestamp),
+        state: update.as_ref().map(|update| update.state),
+    };
+
+    match health.status {
+        "ok" => HttpResponse::Ok().json(health),
+        _ => HttpResponse::ServiceUnavailable().json(health),
+    }
+}
+
+#[get("/widget")]
+async fn widget() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(WIDGET)
+}
+
+#[derive(Deserialize)]
+struct ArtworkQuery {
+    url: String,
+}
+
+// Anything else than the current artwork would let anyone read local files
+#[get("/artwork")]
+async fn cover(latest: web::Data<NowPlaying>, query: web::Query<ArtworkQuery>) -> impl Responder {
+    let current = latest
+        .get()
+        .and_then(|update| update.song)
+        .and_then(|song| song.artwork_url);
+    if current.as_ref() != Some(&query.url) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    match artwork::fetch(&query.url).await {
+        Ok(content) => {
+            let content_type = match image::guess_format(&content) {
+                Ok(format) => format.to_mime_type(),
+                Err(_) => "application/octet-stream",
+            };
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header(("Cache-Control", "max-age=86400"))
+                .body(content)
+        }
+        Err(err) => {
+            log::warn!("Couldn't fetch artwork {}: {}", query.url, err);
+            HttpResponse::BadGateway().finish()
+        }
+    }
+}
+
+fn sse_message(update: &Update) -> web::Bytes {
+    web::Bytes::from(format!("data: {}\n\n", to_json(update)))
+}
+
+// https://html.spec.whatwg.org/multipage/server-sent-events.html
+#[get("/events")]
+async fn events(latest: web::Data<NowPlaying>) -> impl Responder {
+    let (current, receiver) = latest.subscribe();
+    let current = stream::iter(current.map(|update| Ok(sse_message(&update))));
+    let updates = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match tokio::time::timeout(KEEP_ALIVE, receiver.recv()).await {
+                Ok(Ok(update)) => return Some((Ok(sse_message(&update)), receiver)),
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return None,
+                // Comments keep proxies from closing idle connections
+                Err(_) => {
+                    return Some((Ok(web::Bytes::from_static(b": keep-alive\n\n")), receiver));
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming::<_, actix_web::Error>(futures_util::StreamExt::chain(current, updates))
+}
+
+#[get("/ws")]
+async fn websocket(
+    req: HttpRequest,
+    body: web::Payload,
+    latest: web::Data<NowPlaying>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let (current, mut receiver) = latest.subscribe();
+
+    actix_web::rt::spawn(async move {
+        if let Some(update) = current
+            && session.text(to_json(&update)).await.is_err()
+        {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                update = receiver.recv() => match update {
+                    Ok(update) => {
+                        if session.text(to_json(&update)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                message = messages.recv() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    // Clients have nothing to say
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+pub async fn serve(platform: Box<dyn MusicProvider>, sinks: Sinks) -> Result<(), providers::Error> {
+    let platform_name = web::Data::new(platform.name());
+    let mut provider = providers::new(platform);
+    provider.connect().await;
+
+    let latest = web::Data::new(NowPlaying::new());
+    let templates = web::Data::new(Templates::from_env());
+
+    tokio::spawn({
+        let latest = latest.clone();
+        async move {
+            loop {
+                let update = provider.currently_playing().await;
+                let wait_type = update.wait_type();
+                // Clients first, sinks only queue the update anyway
+                latest.set(update.clone());
+                sinks.write(&update);
+                provider.wait(wait_type).await;
+            }
+        }
+    });
+
+    let (ip, port) = get_server_env(IP_ENV, DEFAULT_IP, PORT_ENV, DEFAULT_PORT);
+    log::info!(
+        "Serving the currently playing song on http://{}:{}",
+        ip,
+        port
+    );
+    HttpServer::new(move || {
+        App::new()
+            .app_data(latest.clone())
+            .app_data(templates.clone())
+            .app_data(platform_name.clone())
+            .service(now_playing)
+            .service(now_playing_text)
+            .service(health)
+            .service(events)
+            .service(websocket)
+            .service(widget)
+            .service(cover)
+            .wrap(middleware::Logger::default())
+    })
+    .bind((ip, port))?
+    .run()
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        providers::{Event, Song},
+        template::Template,
+    };
+    use actix_web::{body::MessageBody, dev::ServiceResponse, test};
+    use std::{env, fs, io, pin::pin, process};
+
+    fn update(state: State, artwork_url: Option<String>) -> Update {
+        Update {
+            platform: "Test",
+            timestamp: 1_760_000_000,
+            state,
+            event: Some(Event::TrackChange),
+            song: Some(Song {
+                playing: state == State::Playing,
+                title: "Title".to_string(),
+                artists: vec!["Artist".to_string()],
+                artwork_url,
+                ..Default::default()
+            }),
+            artist: Some("Artist".to_string()),
+            error: None,
+        }
+    }
+
+    async fn request(latest: &web::Data<NowPlaying>, uri: &str) -> ServiceResponse {
+        let template = || Template::parse("{artist} - {title}").unwrap();
+        let templates = Templates {
+            playing: template(),
+            paused: template(),
+            idle: Template::parse("Nothing playing").unwrap(),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(latest.clone())
+                .app_data(web::Data::new(templates))
+                .app_data(web::Data::new("Test"))
+                .service(now_playing)
+                .service(now_playing_text)
+                .service(health)
+                .service(events)
+                .service(cover),
+        )
+        .await;
+        test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await
+    }
+
+    async fn get(latest: &web::Data<NowPlaying>, uri: &str) -> (StatusCode, String) {
+        let response = request(latest, uri).await;
+        let status = response.status();
+        let body = test::read_body(response).await;
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[actix_web::test]
+    async fn unavailable_before_the_first_update() {
+        let latest = web::Data::new(NowPlaying::new());
+
+        let (status, _) = get(&latest, "/now-playing").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, text) = get(&latest, "/now-playing.txt").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(text, "No update from the provider yet");
+        let (status, body) = get(&latest, "/health").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let status_json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status_json["status"], "unavailable");
+        assert!(status_json["last_update"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn serves_the_latest_update() {
+        let latest = web::Data::new(NowPlaying::new());
+        latest.set(update(State::Playing, None));
+
+        let (status, body) = get(&latest, "/now-playing").await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["song"]["title"], "Title");
+        assert_eq!(json["artist"], "Artist");
+
+        let (status, text) = get(&latest, "/now-playing.txt").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(text, "Artist - Title");
+
+        let (status, body) = get(&latest, "/health").await;
+        assert_eq!(status, StatusCode::OK);
+        let status_json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            status_json,
+            serde_json::json!({
+                "status": "ok",
+                "platform": "Test",
+                "last_update": 1_760_000_000,
+                "state": "playing",
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn reports_provider_errors() {
+        let latest = web::Data::new(NowPlaying::new());
+        let mut failed = update(State::Error, None);
+        failed.song = None;
+        failed.error = Some(providers::Error::from(io::Error::other("broken")));
+        latest.set(failed);
+
+        let (status, text) = get(&latest, "/now-playing.txt").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(text, "Unknown: broken");
+        let (status, _) = get(&latest, "/health").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn only_serves_the_current_artwork() {
+        let dir = env::temp_dir().join(format!("imaginal-serve-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cover.png");
+        image::RgbImage::new(4, 4).save(&path).unwrap();
+        let cover_url = format!("file://{}", path.display());
+
+        let latest = web::Data::new(NowPlaying::new());
+        let (status, _) = get(&latest, "/artwork?url=file:///etc/passwd").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        latest.set(update(State::Playing, Some(cover_url.clone())));
+        let (status, _) = get(&latest, "/artwork?url=file:///etc/passwd").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let uri = format!("/artwork?url={}", cover_url.replace('/', "%2F"));
+        let response = request(&latest, &uri).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/png");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn streams_the_current_update_first() {
+        let latest = web::Data::new(NowPlaying::new());
+        latest.set(update(State::Playing, None));
+
+        let response = request(&latest, "/events").await;
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "text/event-stream"
+        );
+        let mut body = pin!(response.into_body());
+        let first = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        let first = String::from_utf8_lossy(&first);
+        assert!(first.starts_with("data: {\"platform\":\"Test\""));
+        assert!(first.ends_with("}\n\n"));
+    }
+}
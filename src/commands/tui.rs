@@ -0,0 +1,289 @@
+use std::{
+    collections::VecDeque,
+    io, process,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Gauge, List, ListItem, Paragraph},
+};
+use tokio::sync::mpsc::{UnboundedReceiver, error::TryRecvError};
+
+use crate::{
+    providers::{
+        self, Event, MusicProvider, RATELIMIT_WAIT_SECS, Song, SongKind, State, Update, WaitType,
+    },
+    sinks::Sinks,
+    template::format_duration,
+};
+
+pub const HISTORY_ARG: &str = "history";
+pub const DEFAULT_HISTORY: &str = "10";
+
+// Redraw often enough for the progress bar to look smooth
+const TICK: Duration = Duration::from_millis(250);
+
+struct Dashboard {
+    platform: &'static str,
+    history_size: usize,
+    state: Option<State>,
+    song: Option<Song>,
+    history: VecDeque<Song>,
+    /// Message and whether it's a rate limit
+    error: Option<(String, bool)>,
+    received: Instant,
+}
+
+impl Dashboard {
+    fn new(platform: &'static str, history_size: usize) -> Self {
+        Dashboard {
+            platform,
+            history_size,
+            state: None,
+            song: None,
+            history: VecDeque::new(),
+            error: None,
+            received: Instant::now(),
+        }
+    }
+
+    fn update(&mut self, update: Update) {
+        self.received = Instant::now();
+        self.state = Some(update.state);
+        let ratelimited = matches!(update.wait_type(), WaitType::Ratelimit);
+        self.error = update.error.map(|err| (err.to_string(), ratelimited));
+
+        // The previous track goes to the history once another one starts
+        if update.event == Some(Event::TrackChange)
+            && let Some(previous) = self.song.take()
+        {
+            self.history.push_front(previous);
+            self.history.truncate(self.history_size);
+        }
+        if update.song.is_some() {
+            self.song = update.song;
+        }
+    }
+
+    /// Progress since the last poll is guessed while playing.
+    fn progress_ms(&self) -> Option<u64> {
+        let song = self.song.as_ref()?;
+        let progress = song.progress_ms?;
+        let progress = match self.state {
+            Some(State::Playing) => progress + self.received.elapsed().as_millis() as u64,
+            _ => progress,
+        };
+        Some(match song.duration_ms {
+            Some(duration) => progress.min(duration),
+            None => progress,
+        })
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [now_playing, progress, status, history] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(self.now_playing(), now_playing);
+        frame.render_widget(self.progress(), progress);
+        frame.render_widget(self.status(), status);
+        frame.render_widget(self.history(), history);
+    }
+
+    fn now_playing(&self) -> Paragraph<'_> {
+        let (label, color) = match self.state {
+            Some(State::Playing) => ("▶ Playing", Color::Green),
+            Some(State::Paused) => ("⏸ Paused", Color::Yellow),
+            Some(State::Idle) => ("⏹ Idle", Color::DarkGray),
+            Some(State::Error) => ("✖ Error", Color::Red),
+            None => ("… Connecting", Color::DarkGray),
+        };
+        let block = Block::bordered()
+            .title(format!(" imaginal · {} ", self.platform))
+            .title_bottom(Line::from(Span::styled(
+                format!(" {} ", label),
+                Style::new().fg(color),
+            )));
+
+        let lines = match (&self.song, self.state) {
+            (Some(song), Some(State::Playing | State::Paused | State::Error)) => song_lines(song),
+            _ => vec![Line::from("No song detected".dark_gray())],
+        };
+        Paragraph::new(lines).block(block)
+    }
+
+    fn progress(&self) -> Gauge<'_> {
+        let duration = self.song.as_ref().and_then(|song| song.duration_ms);
+        let progress = self.progress_ms();
+
+        let ratio = match (progress, duration) {
+            (Some(progress), Some(duration)) if duration > 0 => progress as f64 / duration as f64,
+            _ => 0.0,
+        };
+        let label = match (progress, duration) {
+            _ if self.state == Some(State::Idle) => String::new(),
+            (Some(progress), Some(duration)) => {
+                format!(
+                    "{} / {}",
+                    format_duration(progress),
+                    format_duration(duration)
+                )
+            }
+            (Some(progress), None) => format_duration(progress),
+            (None, Some(duration)) => format!("-:-- / {}", format_duration(duration)),
+            (None, None) => String::new(),
+        };
+
+        Gauge::default()
+            .block(Block::bordered())
+            .gauge_style(Style::new().fg(Color::Cyan))
+            .ratio(ratio.clamp(0.0, 1.0))
+            .label(label)
+    }
+
+    fn status(&self) -> Line<'_> {
+        let elapsed = self.received.elapsed().as_secs();
+        let status = match &self.error {
+            Some((_, true)) => Span::styled(
+                format!(
+                    " Rate limited, retrying in {}s",
+                    RATELIMIT_WAIT_SECS.saturating_sub(elapsed)
+                ),
+                Style::new().fg(Color::Yellow),
+            ),
+            Some((message, _)) => {
+                Span::styled(format!(" {}", message), Style::new().fg(Color::Red))
+            }
+            None if self.state.is_none() => Span::raw(" Waiting for the first update"),
+            None => Span::raw(format!(" Last update {}s ago", elapsed)),
+        };
+        Line::from(vec![status, "  ·  q to quit".dark_gray()])
+    }
+
+    fn history(&self) -> List<'_> {
+        let items: Vec<ListItem> = self
+            .history
+            .iter()
+            .map(|song| ListItem::new(history_line(song)))
+            .collect();
+        List::new(items).block(Block::bordered().title(" History "))
+    }
+}
+
+fn song_lines(song: &Song) -> Vec<Line<'_>> {
+    let title = Line::from(song.title.as_str().bold());
+    match song.kind {
+        SongKind::Track => vec![
+            title,
+            Line::from(song.artist()),
+            Line::from(song.album.as_str().italic()),
+        ],
+        SongKind::Episode => vec![
+            title,
+            Line::from(song.show.clone().unwrap_or_default()),
+            Line::from(song.publisher.clone().unwrap_or_default().italic()),
+        ],
+        SongKind::Ad => vec![Line::from("Advertisement".bold())],
+        SongKind::Unknown => vec![Line::from("Unknown content playing".bold())],
+    }
+}
+
+fn history_line(song: &Song) -> String {
+    match song.kind {
+        SongKind::Track => format!("{} - {}", song.artist(), song.title),
+        SongKind::Episode => format!("{} - {}", song.show.clone().unwrap_or_default(), song.title),
+        SongKind::Ad => "Advertisement".to_string(),
+        SongKind::Unknown => "Unknown content".to_string(),
+    }
+}
+
+fn is_quit(key: &event::KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// Returns the fatal provider error that stopped it, if any.
+fn run(
+    terminal: &mut DefaultTerminal,
+    mut dashboard: Dashboard,
+    mut updates: UnboundedReceiver<Update>,
+) -> io::Result<Option<providers::Error>> {
+    loop {
+        loop {
+            match updates.try_recv() {
+                Ok(Update {
+                    error: Some(err), ..
+                }) if err.is_fatal() => return Ok(Some(err)),
+                Ok(update) => dashboard.update(update),
+                Err(TryRecvError::Empty) => break,
+                // The provider task is gone, nothing left to show
+                Err(TryRecvError::Disconnected) => return Ok(None),
+            }
+        }
+
+        terminal.draw(|frame| dashboard.render(frame))?;
+
+        if event::poll(TICK)?
+            && let TermEvent::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && is_quit(&key)
+        {
+            return Ok(None);
+        }
+    }
+}
+
+pub async fn tui(platform: Box<dyn MusicProvider>, sinks: Sinks, history_size: usize) {
+    let dashboard = Dashboard::new(platform.name(), history_size);
+    let mut provider = providers::new(platform);
+    // Exiting right away would leave the terminal in raw mode
+    provider.set_exit_on_fatal(false);
+    provider.connect().await;
+
+    // Anything written on stderr would garble the screen
+    log::set_max_level(log::LevelFilter::Off);
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let update = provider.currently_playing().await;
+            let wait_type = update.wait_type();
+            let fatal = update.error.as_ref().is_some_and(|err| err.is_fatal());
+            sinks.write(&update);
+            if sender.send(update).is_err() || fatal {
+                break;
+            }
+            provider.wait(wait_type).await;
+        }
+    });
+
+    let result = tokio::task::spawn_blocking(move || {
+        let mut terminal = ratatui::init();
+        let result = run(&mut terminal, dashboard, receiver);
+        ratatui::restore();
+        result
+    })
+    .await;
+
+    match result {
+        Ok(Ok(None)) => {}
+        Ok(Ok(Some(err))) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        Ok(Err(err)) => eprintln!("Terminal error: {}", err),
+        Err(err) => eprintln!("Terminal UI crashed: {}", err),
+    }
+}
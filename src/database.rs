@@ -6,15 +6,11 @@ const DATABASE_FOLDER: &str = "database";
 fn init_folder() -> bool {
     let path = Path::new(DATABASE_FOLDER);
     if path.exists() && path.is_dir() {
-        return true;
+        true
     } else if !path.exists() {
-        let dir = fs::create_dir(DATABASE_FOLDER);
-        if dir.is_err() {
-            return false;
-        }
-        return true;
+        fs::create_dir(DATABASE_FOLDER).is_ok()
     } else {
-        return false;
+        false
     }
 }
 
@@ -107,3 +103,108 @@ pub mod spotify {
         }
     }
 }
+
+pub mod artwork {
+    use std::{fs, path::Path};
+
+    use crate::database::{get_full_path, init_folder};
+
+    const ARTWORK_FOLDER: &str = "artwork";
+
+    // Artwork URLs are long and full of slashes, their hash is used instead
+    pub fn get_artwork_path(url: &str) -> String {
+        get_full_path(&format!("{}/{:x}", ARTWORK_FOLDER, md5::compute(url)))
+    }
+
+    pub fn get_artwork(url: &str) -> Option<Vec<u8>> {
+        let full_path = get_artwork_path(url);
+        let path = Path::new(&full_path);
+
+        if !path.exists() {
+            return None;
+        }
+        match fs::read(path) {
+            Ok(content) => Some(content),
+            Err(_) => {
+                log::error!("Couldn't read {}", full_path);
+                None
+            }
+        }
+    }
+
+    pub fn set_artwork(url: &str, content: &[u8]) -> bool {
+        if !init_folder() || fs::create_dir_all(get_full_path(ARTWORK_FOLDER)).is_err() {
+            log::error!(
+                "Couldn't create or enter `database/{}` folder",
+                ARTWORK_FOLDER
+            );
+            return false;
+        }
+
+        let full_path = get_artwork_path(url);
+        match fs::write(&full_path, content) {
+            Ok(_) => true,
+            Err(_) => {
+                log::error!("Couldn't write to {}", full_path);
+                false
+            }
+        }
+    }
+}
+
+pub mod webhooks {
+    use serde::{Serialize, de::DeserializeOwned};
+    use std::{fs, path::Path};
+
+    use crate::database::{get_full_path, init_folder};
+
+    const QUEUE_FILE: &str = "webhook_queue.json";
+
+    pub fn get_queue<T: DeserializeOwned>() -> Vec<T> {
+        let full_path = get_full_path(QUEUE_FILE);
+        let path = Path::new(&full_path);
+
+        if !path.exists() {
+            return Vec::new();
+        }
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => {
+                log::error!("Couldn't read {}", full_path);
+                return Vec::new();
+            }
+        };
+        match serde_json::from_str(content.as_str()) {
+            Ok(queue) => queue,
+            Err(_) => {
+                log::error!("Couldn't deserialize {}", full_path);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn set_queue<T: Serialize>(queue: &[T]) -> bool {
+        if !init_folder() {
+            log::error!("Couldn't create or enter `database` folder");
+            return false;
+        }
+
+        let content = match serde_json::to_string(queue) {
+            Ok(content) => content,
+            Err(_) => {
+                log::error!("Couldn't serialize the webhook queue");
+                return false;
+            }
+        };
+        // Written aside then renamed, a crash mid-write can't lose the queue
+        let full_path = get_full_path(QUEUE_FILE);
+        let tmp_path = get_full_path(&format!(".{}.tmp", QUEUE_FILE));
+        match fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, &full_path)) {
+            Ok(_) => true,
+            Err(_) => {
+                log::error!("Couldn't write to {}", full_path);
+                false
+            }
+        }
+    }
+}
@@ -1,27 +1,65 @@
+mod artwork;
 mod commands;
 mod database;
+mod output;
 mod providers;
+mod sinks;
+mod template;
 mod utils;
 
-use std::process;
+use std::{process, time::Duration};
 
-use clap::{Command, command};
+use clap::{Arg, ArgAction, Command, command};
 use dotenv::dotenv;
-use log;
+
+const HEARTBEAT_ARG: &str = "heartbeat";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = command!()
         .subcommand(Command::new("connect").about("Connect to OAuth provider platforms"))
+        .subcommand(Command::new("serve").about("Serve the currently playing song over HTTP"))
+        .subcommand(
+            Command::new("tui")
+                .about("Full-screen dashboard of the currently playing song")
+                .arg(
+                    Arg::new(commands::tui::HISTORY_ARG)
+                        .long("history")
+                        .help("Number of previous tracks to show")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value(commands::tui::DEFAULT_HISTORY),
+                ),
+        )
+        .arg(
+            Arg::new(output::OUTPUT_ARG)
+                .long("output")
+                .short('o')
+                .help("Output format")
+                .value_parser(output::OUTPUT_VALUES)
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new(artwork::ARTWORK_ARG)
+                .long("artwork")
+                .help("Show the album artwork above the text output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(HEARTBEAT_ARG)
+                .long("heartbeat")
+                .help("Also output every N seconds when nothing changed")
+                .value_parser(clap::value_parser!(u64)),
+        )
         .get_matches();
 
-    env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
-    );
+    env_logger::init_from_env(env_logger::Env::default().filter_or(
+        env_logger::DEFAULT_FILTER_ENV,
+        "info,zbus=warn,tracing=warn",
+    ));
     dotenv().ok();
     let platform = match providers::detect_platform() {
         Some(p) => {
-            log::debug!("Found platform {}", p);
+            log::debug!("Found platform {}", p.name());
             p
         }
         None => {
@@ -35,12 +73,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             commands::connect::connect(platform).await;
             Ok(())
         }
+        Some(("serve", _)) => {
+            if let Err(err) = commands::serve::serve(platform, sinks::Sinks::from_env()).await {
+                log::error!("Couldn't run the HTTP server: {}", err);
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Some(("tui", tui_matches)) => {
+            let history = *tui_matches
+                .get_one::<usize>(commands::tui::HISTORY_ARG)
+                .unwrap();
+            commands::tui::tui(platform, sinks::Sinks::from_env(), history).await;
+            Ok(())
+        }
         _ => {
+            let output_mode = output::OutputMode::from_arg(
+                matches.get_one::<String>(output::OUTPUT_ARG).unwrap(),
+            );
+            let mut artwork = match (matches.get_flag(artwork::ARTWORK_ARG), &output_mode) {
+                (true, output::OutputMode::Text) => Some(artwork::Artwork::new()),
+                (true, _) => {
+                    log::warn!("--artwork is only available with the text output");
+                    None
+                }
+                _ => None,
+            };
+            let output = output::Output::new(output_mode);
+            let sinks = sinks::Sinks::from_env();
             let mut provider = providers::new(platform);
+            provider.set_heartbeat(
+                matches
+                    .get_one::<u64>(HEARTBEAT_ARG)
+                    .map(|secs| Duration::from_secs(*secs)),
+            );
             provider.connect().await;
             loop {
-                provider.currently_playing().await;
-                provider.wait(providers::WaitType::CurrentlyPlaying);
+                let update = provider.currently_playing().await;
+                if let Some(artwork) = artwork.as_mut() {
+                    artwork.show(&update).await;
+                }
+                output.write(&update);
+                sinks.write(&update);
+                provider.wait(update.wait_type()).await;
             }
         }
     }
@@ -0,0 +1,363 @@
+use serde::Serialize;
+
+use crate::{
+    providers::{State, Update},
+    template::{Template, Templates},
+};
+
+pub const OUTPUT_ARG: &str = "output";
+pub const OUTPUT_VALUES: [&str; 5] = ["text", "json", "waybar", "polybar", "i3blocks"];
+
+pub enum OutputMode {
+    /// Human readable, using the `FORMAT_*` templates
+    Text,
+    /// One JSON object per line
+    Json,
+    /// Waybar custom module with `return-type: json`
+    Waybar,
+    /// Polybar script module with `tail = true`
+    Polybar,
+    /// i3blocks block with `interval=persist` and `format=json`
+    I3blocks,
+}
+
+impl OutputMode {
+    pub fn from_arg(value: &str) -> Self {
+        match value {
+            "json" => OutputMode::Json,
+            "waybar" => OutputMode::Waybar,
+            "polybar" => OutputMode::Polybar,
+            "i3blocks" => OutputMode::I3blocks,
+            _ => OutputMode::Text,
+        }
+    }
+
+    fn is_bar(&self) -> bool {
+        matches!(
+            self,
+            OutputMode::Waybar | OutputMode::Polybar | OutputMode::I3blocks
+        )
+    }
+}
+
+// https://github.com/Alexays/Waybar/wiki/Module:-Custom
+#[derive(Serialize)]
+struct WaybarModule {
+    text: String,
+    tooltip: String,
+    class: State,
+    alt: State,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<u64>,
+}
+
+// https://vivien.github.io/i3blocks/#_format
+#[derive(Serialize)]
+struct I3blocksBlock {
+    full_text: String,
+    short_text: String,
+    urgent: bool,
+}
+
+pub struct Output {
+    mode: OutputMode,
+    templates: Templates,
+    tooltip: Option<Template>,
+}
+
+// Waybar and notification daemons render text as Pango markup
+pub fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn single_line(text: &str) -> String {
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn to_json<T: Serialize>(value: &T) -> Option<String> {
+    match serde_json::to_string(value) {
+        Ok(json) => Some(json),
+        Err(err) => {
+            log::error!("Couldn't serialize output: {}", err);
+            None
+        }
+    }
+}
+
+impl Output {
+    pub fn new(mode: OutputMode) -> Self {
+        let templates = match mode.is_bar() {
+            true => Templates::bar_from_env(),
+            false => Templates::from_env(),
+        };
+        let tooltip = match mode {
+            OutputMode::Waybar => Some(Templates::tooltip_from_env()),
+            _ => None,
+        };
+
+        Output {
+            mode,
+            templates,
+            tooltip,
+        }
+    }
+
+    fn text(&self, update: &Update) -> String {
+        match update.state {
+            State::Error => String::new(),
+            _ => single_line(&self.templates.render(update.song.as_ref(), update.platform)),
+        }
+    }
+
+    fn tooltip(&self, update: &Update) -> String {
+        match (&update.error, &self.tooltip, &update.song) {
+            (Some(err), _, _) => err.to_string(),
+            (None, Some(tooltip), Some(song)) => tooltip.render(Some(song), update.platform),
+            _ => String::new(),
+        }
+    }
+
+    /// Line printed for the update, `None` when nothing should be printed.
+    fn line(&self, update: &Update) -> Option<String> {
+        // Nothing changed since the previous line
+        update.event?;
+
+        match self.mode {
+            // Errors are already logged on stderr
+            OutputMode::Text => match update.state {
+                State::Error => None,
+                _ => Some(self.templates.render(update.song.as_ref(), update.platform)),
+            },
+            OutputMode::Json => to_json(update),
+            OutputMode::Waybar => {
+                let percentage = update.song.as_ref().and_then(|song| {
+                    match (song.progress_ms, song.duration_ms) {
+                        (Some(progress), Some(duration)) if duration > 0 => {
+                            Some((progress * 100 / duration).min(100))
+                        }
+                        _ => None,
+                    }
+                });
+
+                to_json(&WaybarModule {
+                    text: escape_markup(&self.text(update)),
+                    tooltip: escape_markup(&self.tooltip(update)),
+                    class: update.state,
+                    alt: update.state,
+                    percentage,
+                })
+            }
+            // Every line replaces the module content
+            OutputMode::Polybar => Some(self.text(update)),
+            OutputMode::I3blocks => {
+                let short_text = match &update.song {
+                    Some(song) if update.state != State::Error => song.title.clone(),
+                    _ => String::new(),
+                };
+
+                to_json(&I3blocksBlock {
+                    full_text: self.text(update),
+                    short_text,
+                    urgent: update.state == State::Error,
+                })
+            }
+        }
+    }
+
+    pub fn write(&self, update: &Update) {
+        if let Some(line) = self.line(update) {
+            println!("{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{self, Event, Song};
+    use std::io;
+
+    fn output(mode: OutputMode) -> Output {
+        let template = || Template::parse("{artist} - {title}\n\n{album}").unwrap();
+        Output {
+            mode,
+            templates: Templates {
+                playing: template(),
+                paused: template(),
+                idle: Template::parse("Idle").unwrap(),
+            },
+            tooltip: Some(Template::parse("{title} on {platform}").unwrap()),
+        }
+    }
+
+    fn playing() -> Update {
+        Update {
+            platform: "Test",
+            timestamp: 1_760_000_000,
+            state: State::Playing,
+            event: Some(Event::TrackChange),
+            song: Some(Song {
+                playing: true,
+                title: "Rock & <Roll>".to_string(),
+                artists: vec!["Artist".to_string()],
+                album: "Album".to_string(),
+                duration_ms: Some(200_000),
+                progress_ms: Some(50_000),
+                ..Default::default()
+            }),
+            artist: Some("Artist".to_string()),
+            error: None,
+        }
+    }
+
+    fn failed() -> Update {
+        Update {
+            state: State::Error,
+            event: Some(Event::Error),
+            song: None,
+            artist: None,
+            error: Some(providers::Error::from(io::Error::other("broken"))),
+            ..playing()
+        }
+    }
+
+    fn json(mode: OutputMode, update: &Update) -> serde_json::Value {
+        let line = output(mode).line(update).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn nothing_without_an_event() {
+        let update = Update {
+            event: None,
+            ..playing()
+        };
+        for mode in OUTPUT_VALUES {
+            assert_eq!(output(OutputMode::from_arg(mode)).line(&update), None);
+        }
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape_markup("<b>A & B</b>"),
+            "&lt;b&gt;A &amp; B&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn collapses_to_a_single_line() {
+        assert_eq!(single_line("A\n\nB\nC"), "A B C");
+        assert_eq!(
+            output(OutputMode::Polybar).line(&playing()).unwrap(),
+            "Artist - Rock & <Roll> Album"
+        );
+        assert_eq!(output(OutputMode::Polybar).line(&failed()).unwrap(), "");
+    }
+
+    #[test]
+    fn text_keeps_lines_and_skips_errors() {
+        let text = output(OutputMode::Text);
+        assert_eq!(
+            text.line(&playing()).unwrap(),
+            "Artist - Rock & <Roll>\n\nAlbum"
+        );
+        assert_eq!(text.line(&failed()), None);
+    }
+
+    #[test]
+    fn json_lines() {
+        let line = output(OutputMode::Json).line(&playing()).unwrap();
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["platform"], "Test");
+        assert_eq!(value["state"], "playing");
+        assert_eq!(value["event"], "track_change");
+        assert_eq!(value["artist"], "Artist");
+        assert_eq!(value["song"]["title"], "Rock & <Roll>");
+
+        let value = json(OutputMode::Json, &failed());
+        assert_eq!(value["state"], "error");
+        assert!(value["song"].is_null());
+        assert!(value["error"].is_object());
+    }
+
+    #[test]
+    fn waybar_module() {
+        assert_eq!(
+            json(OutputMode::Waybar, &playing()),
+            serde_json::json!({
+                "text": "Artist - Rock &amp; &lt;Roll&gt; Album",
+                "tooltip": "Rock &amp; &lt;Roll&gt; on Test",
+                "class": "playing",
+                "alt": "playing",
+                "percentage": 25,
+            })
+        );
+
+        let idle = Update {
+            state: State::Idle,
+            event: Some(Event::Idle),
+            song: None,
+            artist: None,
+            ..playing()
+        };
+        assert_eq!(
+            json(OutputMode::Waybar, &idle),
+            serde_json::json!({
+                "text": "Idle",
+                "tooltip": "",
+                "class": "idle",
+                "alt": "idle",
+            })
+        );
+
+        let module = json(OutputMode::Waybar, &failed());
+        assert_eq!(module["text"], "");
+        assert_eq!(module["tooltip"], "Unknown: broken");
+        assert_eq!(module["class"], "error");
+    }
+
+    #[test]
+    fn waybar_percentage_needs_a_duration() {
+        let mut update = playing();
+        let song = update.song.as_mut().unwrap();
+        song.duration_ms = Some(0);
+        assert!(
+            json(OutputMode::Waybar, &update)
+                .get("percentage")
+                .is_none()
+        );
+
+        // Providers can report a progress past the end
+        let song = update.song.as_mut().unwrap();
+        song.duration_ms = Some(1_000);
+        song.progress_ms = Some(2_000);
+        assert_eq!(json(OutputMode::Waybar, &update)["percentage"], 100);
+    }
+
+    #[test]
+    fn i3blocks_block() {
+        assert_eq!(
+            json(OutputMode::I3blocks, &playing()),
+            serde_json::json!({
+                "full_text": "Artist - Rock & <Roll> Album",
+                "short_text": "Rock & <Roll>",
+                "urgent": false,
+            })
+        );
+        assert_eq!(
+            json(OutputMode::I3blocks, &failed()),
+            serde_json::json!({
+                "full_text": "",
+                "short_text": "",
+                "urgent": true,
+            })
+        );
+    }
+}
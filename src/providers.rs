@@ -1,50 +1,140 @@
 use std::{
     env,
     fmt::{self, Display},
-    io, thread, time,
+    io, process, time,
 };
 
+use async_trait::async_trait;
+use serde::Serialize;
+
 use crate::utils::check_env_existence;
 
+mod jellyfin;
 mod lastfm;
+mod listenbrainz;
+mod mpd;
+mod mpris;
+mod plex;
 pub mod spotify;
+mod subsonic;
 
 const PRIORITY_PLATFORM: &str = "PRIORITY_PLATFORM";
-const RATELIMIT_WAIT_SECS: u64 = 60;
+const ARTISTS_SEPARATOR: &str = "ARTISTS_SEPARATOR";
+const DEFAULT_ARTISTS_SEPARATOR: &str = ", ";
+const UNKNOWN_ARTIST: &str = "Unknown artist";
+pub const RATELIMIT_WAIT_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SongKind {
+    #[default]
+    Track,
+    Episode,
+    Ad,
+    Unknown,
+}
 
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct Song {
-    playing: bool,
-    title: String,
-    artist: String,
-    album: String,
+    pub kind: SongKind,
+    pub playing: bool,
+    pub title: String,
+    /// Main artist first
+    pub artists: Vec<String>,
+    pub album: String,
+    pub duration_ms: Option<u64>,
+    /// Position in the song when it was retrieved
+    pub progress_ms: Option<u64>,
+    /// Link to the song on the platform
+    pub url: Option<String>,
+    /// Largest available cover art
+    pub artwork_url: Option<String>,
+    /// Platform specific song identifier (Spotify ID, MusicBrainz ID...)
+    pub id: Option<String>,
+    /// Podcast name, for episodes only
+    pub show: Option<String>,
+    /// Podcast publisher, for episodes only
+    pub publisher: Option<String>,
+}
+
+impl Song {
+    /// Every artist joined using `ARTISTS_SEPARATOR`.
+    ///
+    /// `feat.` keeps the first artist as the main one: `A feat. B, C`.
+    pub fn artist(&self) -> String {
+        let separator =
+            env::var(ARTISTS_SEPARATOR).unwrap_or(DEFAULT_ARTISTS_SEPARATOR.to_string());
+        join_artists(&self.artists, separator.as_str())
+    }
+
+    /// Whether both are the same song, whatever the playback state is.
+    pub fn is_same(&self, other: &Song) -> bool {
+        if self.kind != other.kind {
+            return false;
+        }
+        match (&self.id, &other.id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            _ => {
+                self.title == other.title
+                    && self.artists == other.artists
+                    && self.album == other.album
+            }
+        }
+    }
+}
+
+/// First playing song, the first paused one when none is playing.
+///
+/// Used by providers seeing several sessions or players at once.
+pub fn prefer_playing(songs: impl IntoIterator<Item = Song>) -> Option<Song> {
+    let mut paused = None;
+    for song in songs {
+        if song.playing {
+            return Some(song);
+        }
+        if paused.is_none() {
+            paused = Some(song);
+        }
+    }
+    paused
+}
+
+fn join_artists(artists: &[String], separator: &str) -> String {
+    match artists {
+        [] => UNKNOWN_ARTIST.to_string(),
+        [main, featured @ ..] if separator.trim() == "feat." && !featured.is_empty() => {
+            format!("{} feat. {}", main, featured.join(", "))
+        }
+        _ => artists.join(separator),
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum ErrorType {
     ExpiredToken,
     Request,
     WebServer,
     Ratelimit,
+    /// Wrong credentials or settings, retrying won't help
+    Fatal,
     Unknown,
 }
 
 impl Display for ErrorType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let error_type: &str;
-
-        match *self {
-            ErrorType::ExpiredToken => error_type = "ExpiredToken",
-            ErrorType::Request => error_type = "Request",
-            ErrorType::WebServer => error_type = "WebServer",
-            ErrorType::Ratelimit => error_type = "Ratelimit",
-            ErrorType::Unknown => error_type = "Unknown",
-        }
+        let error_type = match *self {
+            ErrorType::ExpiredToken => "ExpiredToken",
+            ErrorType::Request => "Request",
+            ErrorType::WebServer => "WebServer",
+            ErrorType::Ratelimit => "Ratelimit",
+            ErrorType::Fatal => "Fatal",
+            ErrorType::Unknown => "Unknown",
+        };
         write!(f, "{}", error_type)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Error {
     error_type: ErrorType,
     message: String,
@@ -68,6 +158,24 @@ impl From<actix_web::Error> for Error {
     }
 }
 
+impl From<zbus::Error> for Error {
+    fn from(error: zbus::Error) -> Self {
+        Error {
+            error_type: ErrorType::Request,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<zbus::fdo::Error> for Error {
+    fn from(error: zbus::fdo::Error) -> Self {
+        Error {
+            error_type: ErrorType::Request,
+            message: error.to_string(),
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Error {
@@ -77,132 +185,240 @@ impl From<io::Error> for Error {
     }
 }
 
+impl Error {
+    pub fn is_fatal(&self) -> bool {
+        self.error_type == ErrorType::Fatal
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}: {}", self.error_type, self.message)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Platform {
-    Spotify,
-    LastFM,
-}
+impl std::error::Error for Error {}
 
-#[derive(Clone, Default)]
-pub struct PlatformParameters {
-    spotify_access_token: Option<String>,
-    spotify_refresh_token: Option<String>,
-}
+/// A source of currently playing songs.
+///
+/// Implementors keep whatever state they need (tokens, sockets...) and are
+/// registered in [`available`] to be picked up by [`detect_platform`].
+#[async_trait]
+pub trait MusicProvider: Send + Sync {
+    /// Name used in logs and matched against `PRIORITY_PLATFORM`.
+    fn name(&self) -> &'static str;
 
-impl Platform {
-    async fn connect(&self) -> Result<Option<PlatformParameters>, Error> {
-        match *self {
-            Platform::Spotify => spotify::connection::connect().await,
-            _ => {
-                log::warn!("No login implementation detected for {}", self);
-                Ok(None)
-            }
-        }
+    /// Checks that the required environment is present.
+    fn verify(&self, exit: bool) -> bool;
+
+    /// Seconds to wait between two `currently_playing` calls.
+    fn ratelimit(&self) -> u64 {
+        2
     }
 
-    async fn refresh(
-        &self,
-        parameters: Option<PlatformParameters>,
-    ) -> Result<Option<PlatformParameters>, Error> {
-        match *self {
-            Platform::Spotify => spotify::connection::refresh(parameters.clone()).await,
-            _ => {
-                log::warn!("No refresh implementation detected for {}", self);
-                Ok(None)
-            }
-        }
+    async fn connect(&mut self) -> Result<(), Error> {
+        log::warn!("No login implementation detected for {}", self.name());
+        Ok(())
     }
 
-    fn verify(&self) -> bool {
-        match *self {
-            Platform::Spotify => spotify::verify(true),
-            Platform::LastFM => lastfm::verify(true),
-        }
+    async fn refresh(&mut self) -> Result<(), Error> {
+        log::warn!("No refresh implementation detected for {}", self.name());
+        Ok(())
     }
 
-    fn ratelimit(&self) -> u64 {
-        match *self {
-            Platform::Spotify => 2,
-            Platform::LastFM => 2,
+    async fn currently_playing(&self) -> Result<Option<Song>, Error>;
+
+    /// Returns once `currently_playing` is worth calling again.
+    /// Push-capable providers can override this to wake up on changes only.
+    async fn wait_for_change(&mut self) {
+        tokio::time::sleep(time::Duration::from_secs(self.ratelimit())).await;
+    }
+
+    /// Runs the interactive login flow and stores the obtained credentials.
+    /// Returns `false` if the provider doesn't need one.
+    async fn login_server(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+pub enum WaitType {
+    CurrentlyPlaying,
+    Ratelimit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Playing,
+    Paused,
+    Idle,
+    Error,
+}
+
+/// What changed since the previous update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    TrackChange,
+    Pause,
+    Resume,
+    Idle,
+    Error,
+    /// Nothing changed but `--heartbeat` seconds went by
+    Heartbeat,
+}
+
+impl Event {
+    /// Same as the serialized name, used in user configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::TrackChange => "track_change",
+            Event::Pause => "pause",
+            Event::Resume => "resume",
+            Event::Idle => "idle",
+            Event::Error => "error",
+            Event::Heartbeat => "heartbeat",
         }
     }
+}
 
-    async fn currently_playing(
-        &self,
-        parameters: Option<PlatformParameters>,
-    ) -> Result<Option<Song>, Error> {
-        match *self {
-            Platform::Spotify => spotify::playing::currently_playing(parameters).await,
-            Platform::LastFM => lastfm::currently_playing().await,
+/// Result of a single `currently_playing` call.
+#[derive(Debug, Clone, Serialize)]
+pub struct Update {
+    pub platform: &'static str,
+    /// Seconds since UNIX epoch
+    pub timestamp: u64,
+    pub state: State,
+    /// `None` when nothing changed, the update shouldn't be emitted
+    pub event: Option<Event>,
+    pub song: Option<Song>,
+    /// Track artists joined like the `{artist}` template field
+    pub artist: Option<String>,
+    pub error: Option<Error>,
+}
+
+impl Update {
+    fn new(platform: &'static str, result: Result<Option<Song>, Error>) -> Self {
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let (state, song, error) = match result {
+            Ok(Some(song)) if song.playing => (State::Playing, Some(song), None),
+            Ok(Some(song)) => (State::Paused, Some(song), None),
+            Ok(None) => (State::Idle, None, None),
+            Err(err) => (State::Error, None, Some(err)),
+        };
+
+        // Ads and episodes don't have any artist
+        let artist = song
+            .as_ref()
+            .filter(|song| song.kind == SongKind::Track)
+            .map(Song::artist);
+
+        Update {
+            platform,
+            timestamp,
+            state,
+            event: None,
+            song,
+            artist,
+            error,
         }
     }
 
-    pub async fn login_server(
+    /// `previous_state` is the last state that wasn't an error. Recovering
+    /// from an error emits the current state again so consumers leave it.
+    fn detect_event(
         &self,
-    ) -> Result<Option<spotify::connection::AccessTokenJson>, Error> {
-        match *self {
-            Platform::Spotify => Ok(Some(spotify::connection::login_server().await?)),
-            _ => Ok(None),
+        previous_state: Option<State>,
+        previous_error: Option<ErrorType>,
+        previous_song: Option<&Song>,
+    ) -> Option<Event> {
+        // A different error is still worth emitting
+        if let Some(err) = &self.error {
+            return (previous_error != Some(err.error_type)).then_some(Event::Error);
         }
-    }
-}
 
-impl Display for Platform {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let platform: &str;
+        if let Some(song) = &self.song {
+            match previous_song {
+                Some(previous_song) if song.is_same(previous_song) => {}
+                _ => return Some(Event::TrackChange),
+            }
+        }
 
-        match *self {
-            Platform::LastFM => platform = "LastFM",
-            Platform::Spotify => platform = "Spotify",
+        if previous_error.is_none() && previous_state == Some(self.state) {
+            return None;
+        }
+        match self.state {
+            State::Playing => Some(Event::Resume),
+            State::Paused => Some(Event::Pause),
+            State::Idle => Some(Event::Idle),
+            State::Error => Some(Event::Error),
         }
-        write!(f, "{}", platform)
     }
-}
 
-pub enum WaitType {
-    CurrentlyPlaying,
-    Ratelimit,
+    pub fn wait_type(&self) -> WaitType {
+        match &self.error {
+            Some(err) if err.error_type == ErrorType::Ratelimit => WaitType::Ratelimit,
+            _ => WaitType::CurrentlyPlaying,
+        }
+    }
 }
 
 pub struct Provider {
-    platform: Platform,
-    params: Option<PlatformParameters>,
+    platform: Box<dyn MusicProvider>,
+    heartbeat: Option<time::Duration>,
+    exit_on_fatal: bool,
+    /// Last state that wasn't an error
+    previous_state: Option<State>,
+    previous_error: Option<ErrorType>,
+    // Kept through errors and idle so the same song coming back isn't a new track
+    previous_song: Option<Song>,
+    last_emit: time::Instant,
 }
 
 impl Provider {
-    pub fn new(platform: Platform) -> Self {
-        platform.verify();
-        log::info!("Using provider {}", platform);
+    pub fn new(platform: Box<dyn MusicProvider>) -> Self {
+        platform.verify(true);
+        log::info!("Using provider {}", platform.name());
         Self {
-            platform: platform,
-            params: None,
+            platform,
+            heartbeat: None,
+            exit_on_fatal: true,
+            previous_state: None,
+            previous_error: None,
+            previous_song: None,
+            last_emit: time::Instant::now(),
         }
     }
 
+    /// Also emit unchanged updates once `heartbeat` went by without any event.
+    pub fn set_heartbeat(&mut self, heartbeat: Option<time::Duration>) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Return fatal errors as updates instead of exiting, for callers with cleanup to do.
+    pub fn set_exit_on_fatal(&mut self, exit_on_fatal: bool) {
+        self.exit_on_fatal = exit_on_fatal;
+    }
+
     pub async fn connect(&mut self) {
         match self.platform.connect().await {
-            Ok(params) => {
-                self.params = params;
-                log::debug!("Successfully connected to {}", self.platform);
+            Ok(_) => {
+                log::debug!("Successfully connected to {}", self.platform.name());
             }
             Err(err) => {
-                log::error!("Error occured during {} connection", self.platform);
+                log::error!("Error occured during {} connection", self.platform.name());
                 panic!("{}", err);
             }
         }
     }
 
     pub async fn refresh(&mut self) {
-        match self.platform.refresh(self.params.clone()).await {
-            Ok(params) => {
-                self.params = params;
-                log::info!("Successfully connected to {}", self.platform);
+        match self.platform.refresh().await {
+            Ok(_) => {
+                log::info!("Successfully connected to {}", self.platform.name());
             }
             Err(err) => {
                 log::error!("Couldn't refresh access_token using refresh_token");
@@ -211,91 +427,413 @@ impl Provider {
         };
     }
 
-    fn retrieve_params(&self) -> Option<PlatformParameters> {
-        match self.platform {
-            Platform::Spotify => self.params.clone(),
-            Platform::LastFM => None,
+    pub async fn currently_playing(&mut self) -> Update {
+        let mut result = self.platform.currently_playing().await;
+
+        // Expected once in a while, only worth an update if refreshing didn't help
+        if let Err(err) = &result
+            && err.error_type == ErrorType::ExpiredToken
+        {
+            log::info!("{}", err);
+            self.refresh().await;
+            result = self.platform.currently_playing().await;
+        }
+        if let Err(err) = &result {
+            log::error!("{}", err);
+            if err.error_type == ErrorType::Fatal && self.exit_on_fatal {
+                process::exit(1);
+            }
         }
-    }
 
-    pub async fn currently_playing(&mut self) {
-        let params = self.retrieve_params();
+        let mut update = Update::new(self.platform.name(), result);
+        update.event = update.detect_event(
+            self.previous_state,
+            self.previous_error,
+            self.previous_song.as_ref(),
+        );
+
+        if let (None, Some(heartbeat)) = (update.event, self.heartbeat)
+            && self.last_emit.elapsed() >= heartbeat
+        {
+            update.event = Some(Event::Heartbeat);
+        }
 
-        match self.platform.currently_playing(params).await {
-            Ok(currently_playing) => {
-                match currently_playing {
-                    Some(song) => {
-                        println!("{} - {}", song.title, song.artist);
-                        println!("Album: {}", song.album);
-                    }
-                    None => {
-                        println!("No song detected");
-                    }
-                };
-            }
-            Err(err) => {
-                match err.error_type {
-                    ErrorType::ExpiredToken => {
-                        log::info!("{}", err)
-                    }
-                    _ => {
-                        log::error!("{}", err)
-                    }
-                };
-                match err.error_type {
-                    ErrorType::ExpiredToken => {
-                        self.refresh().await;
-                    }
-                    ErrorType::Ratelimit => {
-                        self.wait(WaitType::Ratelimit);
-                    }
-                    _ => {}
-                }
-            }
+        if let Some(event) = update.event {
+            log::debug!("{:?} event", event);
+            self.last_emit = time::Instant::now();
+        }
+        self.previous_error = update.error.as_ref().map(|err| err.error_type);
+        if update.state != State::Error {
+            self.previous_state = Some(update.state);
         }
+        if update.song.is_some() {
+            self.previous_song = update.song.clone();
+        }
+        update
     }
 
-    pub fn wait(&self, wait_type: WaitType) {
-        let mut log_level = log::Level::Debug;
-        let duration = match wait_type {
-            WaitType::CurrentlyPlaying => time::Duration::from_secs(self.platform.ratelimit()),
+    pub async fn wait(&mut self, wait_type: WaitType) {
+        match wait_type {
+            WaitType::CurrentlyPlaying => {
+                log::debug!("Waiting for {} changes", self.platform.name());
+                self.platform.wait_for_change().await;
+            }
             WaitType::Ratelimit => {
-                log_level = log::Level::Warn;
-                time::Duration::from_secs(RATELIMIT_WAIT_SECS)
+                let duration = time::Duration::from_secs(RATELIMIT_WAIT_SECS);
+                log::warn!("Waiting {:?}", duration);
+                tokio::time::sleep(duration).await;
             }
-        };
-
-        log::log!(log_level, "Waiting {:?}", duration);
-        thread::sleep(duration);
+        }
     }
 }
 
-pub fn new(platform: Platform) -> Provider {
+pub fn new(platform: Box<dyn MusicProvider>) -> Provider {
     Provider::new(platform)
 }
 
-fn get_platform_from_env() -> Option<Platform> {
+/// Every known provider, in detection order.
+///
+/// In-house providers only need to be added here.
+pub fn available() -> Vec<Box<dyn MusicProvider>> {
+    vec![
+        Box::new(lastfm::LastFM),
+        Box::new(listenbrainz::ListenBrainz),
+        Box::new(spotify::Spotify::default()),
+        Box::new(subsonic::Subsonic),
+        Box::new(jellyfin::Jellyfin),
+        Box::new(plex::Plex),
+        Box::new(mpd::Mpd::default()),
+        Box::new(mpris::Mpris::default()),
+    ]
+}
+
+fn get_platform_from_env() -> Option<Box<dyn MusicProvider>> {
     let platform_env = env::var(PRIORITY_PLATFORM).unwrap().to_lowercase();
 
-    if platform_env.contains("lastfm") {
-        return Some(Platform::LastFM);
-    }
-    if platform_env.contains("spotify") {
-        return Some(Platform::Spotify);
-    }
-    None
+    available()
+        .into_iter()
+        .find(|p| platform_env.contains(&p.name().to_lowercase()))
 }
 
-pub fn detect_platform() -> Option<Platform> {
+pub fn detect_platform() -> Option<Box<dyn MusicProvider>> {
     log::debug!("Trying to detect platform using env");
     if check_env_existence(PRIORITY_PLATFORM, false) {
         return get_platform_from_env();
     }
-    if lastfm::verify(false) {
-        return Some(Platform::LastFM);
+    available().into_iter().find(|p| p.verify(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    fn song(title: &str, playing: bool) -> Song {
+        Song {
+            playing,
+            title: title.to_string(),
+            artists: vec!["Artist".to_string()],
+            album: "Album".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn error(error_type: ErrorType) -> Error {
+        Error {
+            error_type,
+            message: "failed".to_string(),
+        }
+    }
+
+    fn event(
+        result: Result<Option<Song>, Error>,
+        previous_state: Option<State>,
+        previous_error: Option<ErrorType>,
+        previous_song: Option<&Song>,
+    ) -> Option<Event> {
+        Update::new("Test", result).detect_event(previous_state, previous_error, previous_song)
+    }
+
+    #[test]
+    fn detects_track_changes() {
+        let a = song("A", true);
+        assert_eq!(
+            event(Ok(Some(a.clone())), None, None, None),
+            Some(Event::TrackChange)
+        );
+        assert_eq!(
+            event(
+                Ok(Some(song("B", true))),
+                Some(State::Playing),
+                None,
+                Some(&a)
+            ),
+            Some(Event::TrackChange)
+        );
+        assert_eq!(
+            event(Ok(Some(a.clone())), Some(State::Playing), None, Some(&a)),
+            None
+        );
     }
-    if spotify::verify(false) {
-        return Some(Platform::Spotify);
+
+    #[test]
+    fn serializes_the_joined_artist() {
+        let mut a = song("A", true);
+        a.artists.push("Guest".to_string());
+        let json = serde_json::to_value(Update::new("Test", Ok(Some(a)))).unwrap();
+        assert_eq!(json["artist"], "Artist, Guest");
+        assert_eq!(json["song"]["artists"][1], "Guest");
+
+        let episode = Song {
+            kind: SongKind::Episode,
+            ..song("Episode", true)
+        };
+        let json = serde_json::to_value(Update::new("Test", Ok(Some(episode)))).unwrap();
+        assert!(json["artist"].is_null());
+        let json = serde_json::to_value(Update::new("Test", Ok(None))).unwrap();
+        assert!(json["artist"].is_null());
+    }
+
+    #[test]
+    fn joins_artists() {
+        let artists = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        let three = artists(&["A", "B", "C"]);
+        assert_eq!(join_artists(&three, ", "), "A, B, C");
+        assert_eq!(join_artists(&three, " & "), "A & B & C");
+        assert_eq!(join_artists(&three, " feat. "), "A feat. B, C");
+        assert_eq!(join_artists(&three, "feat."), "A feat. B, C");
+        // Nobody to feature
+        assert_eq!(join_artists(&artists(&["A"]), " feat. "), "A");
+        assert_eq!(join_artists(&[], " & "), UNKNOWN_ARTIST);
+        assert_eq!(join_artists(&[], " feat. "), UNKNOWN_ARTIST);
+    }
+
+    #[test]
+    fn prefers_the_playing_song() {
+        let picked = prefer_playing(vec![
+            song("Paused", false),
+            song("Playing", true),
+            song("Also playing", true),
+        ]);
+        assert_eq!(picked, Some(song("Playing", true)));
+
+        let picked = prefer_playing(vec![song("First", false), song("Second", false)]);
+        assert_eq!(picked, Some(song("First", false)));
+        assert_eq!(prefer_playing(Vec::new()), None);
+    }
+
+    #[test]
+    fn detects_state_changes() {
+        let a = song("A", true);
+        assert_eq!(
+            event(
+                Ok(Some(song("A", false))),
+                Some(State::Playing),
+                None,
+                Some(&a)
+            ),
+            Some(Event::Pause)
+        );
+        assert_eq!(
+            event(Ok(Some(a.clone())), Some(State::Paused), None, Some(&a)),
+            Some(Event::Resume)
+        );
+        assert_eq!(
+            event(Ok(None), Some(State::Playing), None, Some(&a)),
+            Some(Event::Idle)
+        );
+        assert_eq!(event(Ok(None), Some(State::Idle), None, Some(&a)), None);
+    }
+
+    #[test]
+    fn emits_errors_once_per_type() {
+        let a = song("A", true);
+        let request = || Err(error(ErrorType::Request));
+        assert_eq!(
+            event(request(), Some(State::Playing), None, Some(&a)),
+            Some(Event::Error)
+        );
+        assert_eq!(
+            event(
+                request(),
+                Some(State::Playing),
+                Some(ErrorType::Request),
+                Some(&a)
+            ),
+            None
+        );
+        assert_eq!(
+            event(
+                Err(error(ErrorType::Ratelimit)),
+                Some(State::Playing),
+                Some(ErrorType::Request),
+                Some(&a)
+            ),
+            Some(Event::Error)
+        );
+    }
+
+    #[test]
+    fn recovering_with_the_same_song_emits_its_state() {
+        let a = song("A", true);
+        assert_eq!(
+            event(
+                Ok(Some(a.clone())),
+                Some(State::Playing),
+                Some(ErrorType::Request),
+                Some(&a)
+            ),
+            Some(Event::Resume)
+        );
+        assert_eq!(
+            event(Ok(None), Some(State::Idle), Some(ErrorType::Request), None),
+            Some(Event::Idle)
+        );
+        assert_eq!(
+            event(
+                Ok(Some(song("A", false))),
+                Some(State::Playing),
+                Some(ErrorType::Request),
+                Some(&a)
+            ),
+            Some(Event::Pause)
+        );
+    }
+
+    /// Answers with the scripted results in order, then nothing playing.
+    struct Scripted {
+        results: Mutex<VecDeque<Result<Option<Song>, Error>>>,
+        refreshes: Arc<Mutex<u32>>,
+    }
+
+    #[async_trait]
+    impl MusicProvider for Scripted {
+        fn name(&self) -> &'static str {
+            "Scripted"
+        }
+
+        fn verify(&self, _exit: bool) -> bool {
+            true
+        }
+
+        async fn refresh(&mut self) -> Result<(), Error> {
+            *self.refreshes.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        async fn currently_playing(&self) -> Result<Option<Song>, Error> {
+            self.results.lock().unwrap().pop_front().unwrap_or(Ok(None))
+        }
+    }
+
+    fn provider(results: Vec<Result<Option<Song>, Error>>) -> (Provider, Arc<Mutex<u32>>) {
+        let refreshes = Arc::new(Mutex::new(0));
+        let platform = Scripted {
+            results: Mutex::new(results.into()),
+            refreshes: refreshes.clone(),
+        };
+        (Provider::new(Box::new(platform)), refreshes)
+    }
+
+    async fn events(provider: &mut Provider, count: usize) -> Vec<Option<Event>> {
+        let mut events = Vec::new();
+        for _ in 0..count {
+            events.push(provider.currently_playing().await.event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_refreshed_and_retried() {
+        let a = song("A", true);
+        let (mut provider, refreshes) = provider(vec![
+            Ok(Some(a.clone())),
+            Err(error(ErrorType::ExpiredToken)),
+            Ok(Some(a.clone())),
+            Ok(Some(a)),
+        ]);
+
+        let first = provider.currently_playing().await;
+        assert_eq!(first.event, Some(Event::TrackChange));
+        let refreshed = provider.currently_playing().await;
+        assert_eq!(*refreshes.lock().unwrap(), 1);
+        assert_eq!(refreshed.state, State::Playing);
+        assert_eq!(refreshed.event, None);
+        assert_eq!(events(&mut provider, 1).await, vec![None]);
+    }
+
+    #[tokio::test]
+    async fn recovering_from_errors_resumes() {
+        let a = song("A", true);
+        let (mut provider, _) = provider(vec![
+            Ok(Some(a.clone())),
+            Err(error(ErrorType::Request)),
+            Err(error(ErrorType::Request)),
+            Ok(Some(a)),
+        ]);
+
+        assert_eq!(
+            events(&mut provider, 4).await,
+            vec![
+                Some(Event::TrackChange),
+                Some(Event::Error),
+                None,
+                Some(Event::Resume)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fatal_errors_are_returned_when_not_exiting() {
+        let (mut provider, refreshes) = provider(vec![Err(error(ErrorType::Fatal))]);
+        provider.set_exit_on_fatal(false);
+
+        let update = provider.currently_playing().await;
+        assert_eq!(update.state, State::Error);
+        assert_eq!(update.event, Some(Event::Error));
+        assert!(update.error.is_some_and(|err| err.is_fatal()));
+        assert_eq!(*refreshes.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn heartbeat_only_fills_silent_updates() {
+        let a = song("A", true);
+        let (mut provider, _) = provider(vec![
+            Ok(Some(a.clone())),
+            Ok(Some(a.clone())),
+            Err(error(ErrorType::Request)),
+            Err(error(ErrorType::Request)),
+        ]);
+        provider.set_heartbeat(Some(time::Duration::ZERO));
+
+        assert_eq!(
+            events(&mut provider, 4).await,
+            vec![
+                Some(Event::TrackChange),
+                Some(Event::Heartbeat),
+                Some(Event::Error),
+                Some(Event::Heartbeat)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_heartbeat_before_the_interval() {
+        let a = song("A", true);
+        let (mut provider, _) = provider(vec![Ok(Some(a.clone())), Ok(Some(a))]);
+        provider.set_heartbeat(Some(time::Duration::from_secs(3600)));
+
+        assert_eq!(
+            events(&mut provider, 2).await,
+            vec![Some(Event::TrackChange), None]
+        );
     }
-    None
 }
@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use std::env;
+
+use crate::providers::{self, MusicProvider, Song};
+use crate::utils::check_env_existence;
+
+const URL_ENV: &str = "JELLYFIN_URL";
+const API_KEY_ENV: &str = "JELLYFIN_API_KEY";
+const USERNAME_ENV: &str = "JELLYFIN_USERNAME";
+
+// Understood by both Jellyfin and Emby
+const TOKEN_HEADER: &str = "X-Emby-Token";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Session {
+    user_name: Option<String>,
+    now_playing_item: Option<NowPlayingItem>,
+    play_state: Option<PlayState>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NowPlayingItem {
+    name: String,
+    #[serde(rename = "Type")]
+    item_type: String,
+    #[serde(default)]
+    artists: Vec<String>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    id: Option<String>,
+    run_time_ticks: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PlayState {
+    #[serde(default)]
+    is_paused: bool,
+    position_ticks: Option<u64>,
+}
+
+// Jellyfin ticks are 100 nanoseconds
+const TICKS_PER_MS: u64 = 10_000;
+
+pub struct Jellyfin;
+
+#[async_trait]
+impl MusicProvider for Jellyfin {
+    fn name(&self) -> &'static str {
+        "Jellyfin"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        currently_playing().await
+    }
+}
+
+pub fn verify(exit: bool) -> bool {
+    check_env_existence(URL_ENV, exit);
+    check_env_existence(API_KEY_ENV, exit);
+    check_env_existence(USERNAME_ENV, exit)
+}
+
+fn to_song(item: NowPlayingItem, play_state: Option<PlayState>) -> Song {
+    let (paused, position_ticks) = match play_state {
+        Some(play_state) => (play_state.is_paused, play_state.position_ticks),
+        None => (false, None),
+    };
+    let artists = match item.artists.is_empty() {
+        false => item.artists,
+        true => item.album_artist.into_iter().collect(),
+    };
+
+    Song {
+        playing: !paused,
+        title: item.name,
+        artists,
+        album: item.album.unwrap_or_default(),
+        duration_ms: item.run_time_ticks.map(|ticks| ticks / TICKS_PER_MS),
+        progress_ms: position_ticks.map(|ticks| ticks / TICKS_PER_MS),
+        id: item.id,
+        ..Default::default()
+    }
+}
+
+// A playing session of the user wins over a paused one
+fn select(sessions: Vec<Session>, username: &str) -> Option<Song> {
+    let songs = sessions.into_iter().filter_map(|session| {
+        let is_user = match &session.user_name {
+            Some(user_name) => user_name.eq_ignore_ascii_case(username),
+            None => false,
+        };
+        match session.now_playing_item {
+            Some(item) if is_user && item.item_type == "Audio" => {
+                Some(to_song(item, session.play_state))
+            }
+            _ => None,
+        }
+    });
+    providers::prefer_playing(songs)
+}
+
+pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
+    let url = format!(
+        "{}/Sessions",
+        env::var(URL_ENV).unwrap().trim_end_matches('/')
+    );
+    let api_key = env::var(API_KEY_ENV).unwrap();
+    let username = env::var(USERNAME_ENV).unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(TOKEN_HEADER, api_key.parse().unwrap());
+
+    let client = reqwest::Client::new();
+    let response = client.get(url).headers(headers).send().await?;
+
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Fatal,
+                message: "Incorrect API Key".to_string(),
+            });
+        }
+        reqwest::StatusCode::TOO_MANY_REQUESTS => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Ratelimit,
+                message: "Too many requests".to_string(),
+            });
+        }
+        reqwest::StatusCode::OK => {}
+        status_code => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Unknown,
+                message: format!("Unhandled status code {} from Jellyfin", status_code),
+            });
+        }
+    }
+
+    let sessions = response.json::<Vec<Session>>().await?;
+
+    let currently_playing = select(sessions, &username);
+    if currently_playing.is_none() {
+        log::debug!("No audio session for {}", username);
+    }
+    Ok(currently_playing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `/Sessions` with another user, a movie and an idle session around `hugo` songs
+    const SESSIONS: &str = r#"[
+        {
+            "UserName": "other",
+            "NowPlayingItem": {"Name": "Not mine", "Type": "Audio", "Artists": ["Someone"]},
+            "PlayState": {"IsPaused": false}
+        },
+        {
+            "UserName": "Hugo",
+            "NowPlayingItem": {"Name": "A movie", "Type": "Movie", "RunTimeTicks": 72000000000},
+            "PlayState": {"IsPaused": false}
+        },
+        {
+            "UserName": "hugo",
+            "NowPlayingItem": {
+                "Name": "Paused song",
+                "Type": "Audio",
+                "Artists": [],
+                "AlbumArtist": "Album Artist",
+                "Album": "Album",
+                "Id": "paused",
+                "RunTimeTicks": 1800000000
+            },
+            "PlayState": {"IsPaused": true, "PositionTicks": 425000000}
+        },
+        {"UserName": "hugo", "PlayState": {}},
+        {
+            "UserName": "hugo",
+            "NowPlayingItem": {
+                "Name": "Playing song",
+                "Type": "Audio",
+                "Artists": ["Artist", "Guest"],
+                "Id": "playing"
+            },
+            "PlayState": {"IsPaused": false, "PositionTicks": 10000}
+        }
+    ]"#;
+
+    fn sessions() -> Vec<Session> {
+        serde_json::from_str(SESSIONS).unwrap()
+    }
+
+    #[test]
+    fn reads_the_playing_audio_item() {
+        let song = select(sessions(), "hugo").unwrap();
+        assert!(song.playing);
+        assert_eq!(song.title, "Playing song");
+        assert_eq!(song.artists, vec!["Artist", "Guest"]);
+        assert_eq!(song.album, "");
+        assert_eq!(song.progress_ms, Some(1));
+        assert_eq!(song.duration_ms, None);
+    }
+
+    #[test]
+    fn paused_item_when_nothing_plays() {
+        let mut sessions = sessions();
+        sessions.pop();
+
+        let song = select(sessions, "HUGO").unwrap();
+        assert!(!song.playing);
+        assert_eq!(song.title, "Paused song");
+        assert_eq!(song.artists, vec!["Album Artist"]);
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.id.as_deref(), Some("paused"));
+        assert_eq!(song.duration_ms, Some(180_000));
+        assert_eq!(song.progress_ms, Some(42_500));
+    }
+
+    #[test]
+    fn skips_other_users_and_videos() {
+        assert!(select(sessions(), "nobody").is_none());
+
+        let sessions = sessions().into_iter().take(2).collect();
+        assert!(select(sessions, "hugo").is_none());
+    }
+}
@@ -1,7 +1,8 @@
+use async_trait::async_trait;
 use serde::Deserialize;
-use std::{env, process};
+use std::env;
 
-use crate::providers::{self, Song};
+use crate::providers::{self, MusicProvider, Song};
 use crate::utils::check_env_existence;
 
 const API_URL: &str = "http://ws.audioscrobbler.com/2.0/";
@@ -25,6 +26,11 @@ struct Track {
     artist: TextFields,
     album: TextFields,
     name: String,
+    url: String,
+    #[serde(default)]
+    mbid: String,
+    // Sorted from small to extralarge
+    image: Vec<TextFields>,
     #[serde(rename = "@attr")]
     attr: Option<TrackAttr>,
 }
@@ -45,6 +51,23 @@ struct Error {
     error: u16,
 }
 
+pub struct LastFM;
+
+#[async_trait]
+impl MusicProvider for LastFM {
+    fn name(&self) -> &'static str {
+        "LastFM"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        currently_playing().await
+    }
+}
+
 pub fn verify(exit: bool) -> bool {
     check_env_existence(API_KEY_ENV, exit);
     check_env_existence(SHARED_SECRET_ENV, exit);
@@ -73,12 +96,12 @@ pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
 
         match results.error {
             6 => {
-                log::error!("Unknown user");
-                process::exit(1);
+                error_type = providers::ErrorType::Fatal;
+                message = "Unknown user";
             }
             10 => {
-                log::error!("Incorrect API Key");
-                process::exit(1);
+                error_type = providers::ErrorType::Fatal;
+                message = "Incorrect API Key";
             }
             29 => {
                 error_type = providers::ErrorType::Ratelimit;
@@ -88,7 +111,7 @@ pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
         }
 
         return Err(providers::Error {
-            error_type: error_type,
+            error_type,
             message: message.to_string(),
         });
     }
@@ -98,21 +121,25 @@ pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
     let currently_playing = match results.recenttracks.track.into_iter().next() {
         Some(track) => {
             let playing = match track.attr {
-                Some(track_attr) => {
-                    if track_attr.nowplaying == "true" {
-                        true
-                    } else {
-                        false
-                    }
-                }
+                Some(track_attr) => track_attr.nowplaying == "true",
                 _ => false,
             };
 
+            let artwork_url = track
+                .image
+                .into_iter()
+                .map(|image| image.text)
+                .rfind(|url| !url.is_empty());
+
             Some(Song {
                 album: track.album.text,
-                playing: playing,
+                playing,
                 title: track.name,
-                artist: track.artist.text,
+                artists: vec![track.artist.text],
+                url: Some(track.url),
+                artwork_url,
+                id: Some(track.mbid).filter(|mbid| !mbid.is_empty()),
+                ..Default::default()
             })
         }
         None => {
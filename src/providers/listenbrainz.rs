@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use std::env;
+
+use crate::providers::{self, MusicProvider, Song};
+use crate::utils::check_env_existence;
+
+const DEFAULT_API_URL: &str = "https://api.listenbrainz.org";
+const API_URL_ENV: &str = "LISTENBRAINZ_API_URL";
+const TOKEN_ENV: &str = "LISTENBRAINZ_TOKEN";
+const USERNAME_ENV: &str = "LISTENBRAINZ_USERNAME";
+
+#[derive(Deserialize)]
+struct ListensSchema {
+    payload: Payload,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    listens: Vec<Listen>,
+}
+
+#[derive(Deserialize)]
+struct Listen {
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Deserialize)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    release_name: Option<String>,
+    additional_info: Option<AdditionalInfo>,
+}
+
+#[derive(Deserialize, Default)]
+struct AdditionalInfo {
+    artist_names: Option<Vec<String>>,
+    duration_ms: Option<u64>,
+    recording_mbid: Option<String>,
+    origin_url: Option<String>,
+}
+
+pub struct ListenBrainz;
+
+#[async_trait]
+impl MusicProvider for ListenBrainz {
+    fn name(&self) -> &'static str {
+        "ListenBrainz"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        currently_playing().await
+    }
+}
+
+pub fn verify(exit: bool) -> bool {
+    check_env_existence(TOKEN_ENV, exit);
+    check_env_existence(USERNAME_ENV, exit)
+}
+
+fn get_api_url() -> String {
+    match env::var(API_URL_ENV) {
+        Ok(url) => url.trim_end_matches('/').to_string(),
+        Err(_) => DEFAULT_API_URL.to_string(),
+    }
+}
+
+async fn get_listen(
+    api_url: &str,
+    username: &str,
+    token: &str,
+    endpoint: &str,
+    count: bool,
+) -> Result<Option<Listen>, providers::Error> {
+    let url = format!("{}/1/user/{}/{}", api_url, username, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        reqwest::header::AUTHORIZATION,
+        format!("Token {}", token).parse().unwrap(),
+    );
+
+    let client = reqwest::Client::new();
+    let mut request = client.get(url).headers(headers);
+    if count {
+        request = request.query(&[("count", "1")]);
+    }
+    let response = request.send().await?;
+
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Fatal,
+                message: "Incorrect ListenBrainz token".to_string(),
+            });
+        }
+        reqwest::StatusCode::NOT_FOUND => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Fatal,
+                message: "Unknown user".to_string(),
+            });
+        }
+        reqwest::StatusCode::TOO_MANY_REQUESTS => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Ratelimit,
+                message: "Too many requests".to_string(),
+            });
+        }
+        reqwest::StatusCode::OK => {}
+        status_code => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Unknown,
+                message: format!("Unhandled status code {} from ListenBrainz", status_code),
+            });
+        }
+    }
+
+    let results = response.json::<ListensSchema>().await?;
+    Ok(results.payload.listens.into_iter().next())
+}
+
+fn to_song(listen: Listen, playing: bool) -> Song {
+    let metadata = listen.track_metadata;
+    let additional_info = metadata.additional_info.unwrap_or_default();
+
+    Song {
+        playing,
+        title: metadata.track_name,
+        artists: additional_info
+            .artist_names
+            .unwrap_or(vec![metadata.artist_name]),
+        album: metadata.release_name.unwrap_or_default(),
+        duration_ms: additional_info.duration_ms,
+        url: additional_info.origin_url,
+        id: additional_info.recording_mbid,
+        ..Default::default()
+    }
+}
+
+pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
+    let username = env::var(USERNAME_ENV).unwrap();
+    let token = env::var(TOKEN_ENV).unwrap();
+    currently_playing_with(&get_api_url(), &username, &token).await
+}
+
+async fn currently_playing_with(
+    api_url: &str,
+    username: &str,
+    token: &str,
+) -> Result<Option<Song>, providers::Error> {
+    if let Some(listen) = get_listen(api_url, username, token, "playing-now", false).await? {
+        return Ok(Some(to_song(listen, true)));
+    }
+
+    // Same behaviour as LastFM, show the last listen when nothing is playing
+    match get_listen(api_url, username, token, "listens", true).await? {
+        Some(listen) => Ok(Some(to_song(listen, false))),
+        None => {
+            log::debug!("No listens detected at all");
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+
+    const PLAYING_NOW: &str = r#"{
+        "payload": {
+            "count": 1,
+            "playing_now": true,
+            "listens": [{
+                "playing_now": true,
+                "track_metadata": {
+                    "artist_name": "Artist feat. Guest",
+                    "track_name": "Playing",
+                    "release_name": "Album",
+                    "additional_info": {
+                        "artist_names": ["Artist", "Guest"],
+                        "duration_ms": 180000,
+                        "recording_mbid": "mbid",
+                        "origin_url": "https://example.com/track"
+                    }
+                }
+            }]
+        }
+    }"#;
+    const LISTENS: &str = r#"{
+        "payload": {
+            "count": 1,
+            "listens": [{
+                "listened_at": 1700000000,
+                "track_metadata": {"artist_name": "Artist", "track_name": "Listened"}
+            }]
+        }
+    }"#;
+    const EMPTY: &str = r#"{"payload": {"count": 0, "listens": []}}"#;
+
+    /// Fake API where `playing` is listening, `idle` only has past listens, `new` has none,
+    /// `busy` is rate limited and `broken` gets a server error.
+    fn api() -> String {
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/1/user/{user}/{endpoint}",
+                web::get().to(
+                    |request: HttpRequest, path: web::Path<(String, String)>| async move {
+                        let token = request.headers().get("Authorization");
+                        if token.is_none_or(|token| token != "Token token") {
+                            return HttpResponse::Unauthorized().finish();
+                        }
+                        let body = match (path.0.as_str(), path.1.as_str()) {
+                            ("playing", "playing-now") => PLAYING_NOW,
+                            ("idle", "listens") if request.query_string() == "count=1" => LISTENS,
+                            ("playing" | "idle" | "new", _) => EMPTY,
+                            ("busy", _) => return HttpResponse::TooManyRequests().finish(),
+                            ("broken", _) => return HttpResponse::BadGateway().finish(),
+                            _ => return HttpResponse::NotFound().finish(),
+                        };
+                        HttpResponse::Ok()
+                            .content_type("application/json")
+                            .body(body)
+                    },
+                ),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        url
+    }
+
+    #[actix_web::test]
+    async fn reads_playing_now_then_the_last_listen() {
+        let api = api();
+
+        let song = currently_playing_with(&api, "playing", "token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(song.playing);
+        assert_eq!(song.title, "Playing");
+        assert_eq!(song.artists, vec!["Artist", "Guest"]);
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.duration_ms, Some(180_000));
+        assert_eq!(song.id.as_deref(), Some("mbid"));
+        assert_eq!(song.url.as_deref(), Some("https://example.com/track"));
+
+        let song = currently_playing_with(&api, "idle", "token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!song.playing);
+        assert_eq!(song.title, "Listened");
+        assert_eq!(song.artists, vec!["Artist"]);
+        assert_eq!(song.album, "");
+
+        let song = currently_playing_with(&api, "new", "token").await.unwrap();
+        assert!(song.is_none());
+    }
+
+    #[actix_web::test]
+    async fn maps_error_statuses() {
+        let api = api();
+        let error = |username: &'static str, token: &'static str| {
+            let api = api.clone();
+            async move {
+                currently_playing_with(&api, username, token)
+                    .await
+                    .unwrap_err()
+            }
+        };
+
+        let err = error("playing", "wrong").await;
+        assert_eq!(err.error_type, providers::ErrorType::Fatal);
+        assert_eq!(err.message, "Incorrect ListenBrainz token");
+        let err = error("unknown", "token").await;
+        assert_eq!(err.error_type, providers::ErrorType::Fatal);
+        assert_eq!(err.message, "Unknown user");
+        let err = error("busy", "token").await;
+        assert_eq!(err.error_type, providers::ErrorType::Ratelimit);
+        let err = error("broken", "token").await;
+        assert_eq!(err.error_type, providers::ErrorType::Unknown);
+    }
+}
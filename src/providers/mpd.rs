@@ -0,0 +1,373 @@
+use async_trait::async_trait;
+use std::{env, process, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixStream},
+    sync::Mutex,
+};
+
+use crate::providers::{self, MusicProvider, Song};
+use crate::utils::check_env_existence;
+
+const HOST_ENV: &str = "MPD_HOST";
+const PORT_ENV: &str = "MPD_PORT";
+const PASSWORD_ENV: &str = "MPD_PASSWORD";
+const DEFAULT_PORT: u16 = 6600;
+
+// Idle is interrupted once in a while so a dead connection gets noticed
+const IDLE_TIMEOUT_SECS: u64 = 60;
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+struct Connection {
+    stream: BufReader<Box<dyn Stream>>,
+}
+
+type Response = Vec<(String, String)>;
+
+impl Connection {
+    async fn open() -> Result<Self, providers::Error> {
+        let host = env::var(HOST_ENV).unwrap();
+        // Same convention as `mpc`: MPD_HOST=password@host
+        let (password, host) = match host.split_once('@') {
+            Some((password, host)) => (Some(password.to_string()), host.to_string()),
+            None => (env::var(PASSWORD_ENV).ok(), host),
+        };
+
+        let stream: Box<dyn Stream> = if host.starts_with('/') {
+            log::debug!("Connecting to MPD socket {}", host);
+            Box::new(UnixStream::connect(host).await?)
+        } else {
+            let port: u16 = match env::var(PORT_ENV).map(|port| port.parse()) {
+                Ok(Ok(port)) => port,
+                Ok(Err(_)) => {
+                    log::error!("{} must be a port number", PORT_ENV);
+                    process::exit(1);
+                }
+                Err(_) => DEFAULT_PORT,
+            };
+            log::debug!("Connecting to MPD at {}:{}", host, port);
+            Box::new(TcpStream::connect((host, port)).await?)
+        };
+
+        let mut connection = Connection {
+            stream: BufReader::new(stream),
+        };
+
+        let greeting = connection.read_line().await?;
+        if !greeting.starts_with("OK MPD") {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Request,
+                message: format!("Unexpected MPD greeting: {}", greeting),
+            });
+        }
+
+        if let Some(password) = password {
+            connection
+                .command(format!("password {}", password).as_str())
+                .await?;
+        }
+        Ok(connection)
+    }
+
+    async fn read_line(&mut self) -> Result<String, providers::Error> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Request,
+                message: "MPD closed the connection".to_string(),
+            });
+        }
+        Ok(line.trim_end_matches('\n').to_string())
+    }
+
+    async fn read_response(&mut self) -> Result<Response, providers::Error> {
+        let mut response = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == "OK" {
+                return Ok(response);
+            }
+            if line.starts_with("ACK") {
+                return Err(providers::Error {
+                    error_type: providers::ErrorType::Request,
+                    message: line,
+                });
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                response.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Response, providers::Error> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+        self.read_response().await
+    }
+
+    async fn idle(&mut self) -> Result<(), providers::Error> {
+        self.stream.get_mut().write_all(b"idle player\n").await?;
+
+        let timeout = Duration::from_secs(IDLE_TIMEOUT_SECS);
+        match tokio::time::timeout(timeout, self.read_response()).await {
+            Ok(response) => response.map(|_| ()),
+            Err(_) => {
+                log::debug!("No MPD player event in {:?}", timeout);
+                self.command("noidle").await.map(|_| ())
+            }
+        }
+    }
+}
+
+// Tags like `Artist` can be repeated
+fn get_fields(response: &Response, key: &str) -> Vec<String> {
+    response
+        .iter()
+        .filter(|(k, _)| k == key)
+        .map(|(_, v)| v.clone())
+        .collect()
+}
+
+fn get_field(response: &Response, key: &str) -> Option<String> {
+    response
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.clone())
+}
+
+// MPD durations are in seconds with a millisecond precision
+fn get_millis(response: &Response, key: &str) -> Option<u64> {
+    get_field(response, key)
+        .and_then(|value| value.parse::<f64>().ok())
+        .map(|secs| (secs * 1000.0).round() as u64)
+}
+
+#[derive(Default)]
+pub struct Mpd {
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Mpd {
+    async fn currently_playing_with(
+        connection: &mut Connection,
+    ) -> Result<Option<Song>, providers::Error> {
+        let status = connection.command("status").await?;
+        let playing = match get_field(&status, "state").as_deref() {
+            Some("play") => true,
+            Some("pause") => false,
+            _ => {
+                log::debug!("MPD is stopped");
+                return Ok(None);
+            }
+        };
+
+        let song = connection.command("currentsong").await?;
+        let title = match get_field(&song, "Title").or_else(|| get_field(&song, "file")) {
+            Some(title) => title,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Song {
+            playing,
+            title,
+            artists: get_fields(&song, "Artist"),
+            album: get_field(&song, "Album").unwrap_or_default(),
+            duration_ms: get_millis(&status, "duration"),
+            progress_ms: get_millis(&status, "elapsed"),
+            id: get_field(&song, "MUSICBRAINZ_TRACKID"),
+            ..Default::default()
+        }))
+    }
+}
+
+#[async_trait]
+impl MusicProvider for Mpd {
+    fn name(&self) -> &'static str {
+        "MPD"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn connect(&mut self) -> Result<(), providers::Error> {
+        *self.connection.get_mut() = Some(Connection::open().await?);
+        Ok(())
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        let mut guard = self.connection.lock().await;
+        if guard.is_none() {
+            *guard = Some(Connection::open().await?);
+        }
+
+        let result = Self::currently_playing_with(guard.as_mut().unwrap()).await;
+        if result.is_err() {
+            // Reconnect on next call
+            *guard = None;
+        }
+        result
+    }
+
+    async fn wait_for_change(&mut self) {
+        let result = match self.connection.get_mut() {
+            Some(connection) => connection.idle().await,
+            None => {
+                tokio::time::sleep(Duration::from_secs(self.ratelimit())).await;
+                return;
+            }
+        };
+
+        if let Err(err) = result {
+            log::error!("{}", err);
+            *self.connection.get_mut() = None;
+            tokio::time::sleep(Duration::from_secs(self.ratelimit())).await;
+        }
+    }
+}
+
+pub fn verify(exit: bool) -> bool {
+    check_env_existence(HOST_ENV, exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{self, AsyncBufReadExt};
+
+    /// Fake MPD server answering each known command with its scripted reply.
+    fn connection(replies: Vec<(&'static str, &'static str)>) -> Connection {
+        let (client, server) = io::duplex(4096);
+        tokio::spawn(async move {
+            let (read, mut write) = io::split(server);
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(command)) = lines.next_line().await {
+                if let Some((_, reply)) = replies.iter().find(|(known, _)| *known == command) {
+                    write.write_all(reply.as_bytes()).await.unwrap();
+                }
+            }
+        });
+        Connection {
+            stream: BufReader::new(Box::new(client)),
+        }
+    }
+
+    fn status(state: &str) -> &'static str {
+        match state {
+            "play" => "volume: 50\nstate: play\nelapsed: 12.345\nduration: 201.500\nOK\n",
+            "pause" => "state: pause\nelapsed: 1.000\nduration: 201.500\nOK\n",
+            _ => "state: stop\nOK\n",
+        }
+    }
+
+    const CURRENT_SONG: &str = "file: music/song.flac\nArtist: First\nArtist: Second\nTitle: Song\nAlbum: Album\nMUSICBRAINZ_TRACKID: abc\nOK\n";
+
+    #[tokio::test]
+    async fn reads_responses_until_ok() {
+        let mut connection = connection(vec![("currentsong", CURRENT_SONG)]);
+        let response = connection.command("currentsong").await.unwrap();
+
+        assert_eq!(response.len(), 6);
+        assert_eq!(get_field(&response, "Title").as_deref(), Some("Song"));
+        assert_eq!(get_fields(&response, "Artist"), vec!["First", "Second"]);
+        assert_eq!(get_field(&response, "Missing"), None);
+    }
+
+    #[tokio::test]
+    async fn ack_is_an_error() {
+        let mut connection = connection(vec![(
+            "password wrong",
+            "ACK [3@0] {password} incorrect password\n",
+        )]);
+        let err = connection.command("password wrong").await.unwrap_err();
+
+        assert_eq!(err.error_type, providers::ErrorType::Request);
+        assert!(err.message.contains("incorrect password"));
+    }
+
+    #[test]
+    fn converts_seconds_to_millis() {
+        let response = vec![
+            ("elapsed".to_string(), "12.345".to_string()),
+            ("duration".to_string(), "nope".to_string()),
+        ];
+        assert_eq!(get_millis(&response, "elapsed"), Some(12345));
+        assert_eq!(get_millis(&response, "duration"), None);
+        assert_eq!(get_millis(&response, "missing"), None);
+    }
+
+    #[tokio::test]
+    async fn playing_song() {
+        let mut connection = connection(vec![
+            ("status", status("play")),
+            ("currentsong", CURRENT_SONG),
+        ]);
+        let song = Mpd::currently_playing_with(&mut connection)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(song.playing);
+        assert_eq!(song.title, "Song");
+        assert_eq!(song.artists, vec!["First", "Second"]);
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.progress_ms, Some(12345));
+        assert_eq!(song.duration_ms, Some(201500));
+        assert_eq!(song.id.as_deref(), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn paused_song_without_tags() {
+        let mut connection = connection(vec![
+            ("status", status("pause")),
+            ("currentsong", "file: music/song.flac\nOK\n"),
+        ]);
+        let song = Mpd::currently_playing_with(&mut connection)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(!song.playing);
+        assert_eq!(song.title, "music/song.flac");
+        assert!(song.artists.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stopped_is_nothing_playing() {
+        let mut connection = connection(vec![("status", status("stop"))]);
+        let song = Mpd::currently_playing_with(&mut connection).await.unwrap();
+
+        assert_eq!(song, None);
+    }
+
+    #[tokio::test]
+    async fn idle_returns_on_player_events() {
+        let mut connection = connection(vec![("idle player", "changed: player\nOK\n")]);
+        connection.idle().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_times_out_with_noidle() {
+        // No answer to `idle`, MPD only answers `noidle` once interrupted
+        let mut connection = connection(vec![("noidle", "OK\n")]);
+        let start = tokio::time::Instant::now();
+        connection.idle().await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_secs(IDLE_TIMEOUT_SECS));
+    }
+
+    #[tokio::test]
+    async fn closed_connection_is_an_error() {
+        let (client, server) = io::duplex(64);
+        drop(server);
+        let mut connection = Connection {
+            stream: BufReader::new(Box::new(client)),
+        };
+
+        assert!(connection.command("status").await.is_err());
+    }
+}
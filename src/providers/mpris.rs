@@ -0,0 +1,266 @@
+use async_trait::async_trait;
+use std::{collections::HashMap, env};
+use zbus::{
+    Connection,
+    fdo::{DBusProxy, PropertiesProxy},
+    names::InterfaceName,
+    zvariant::OwnedValue,
+};
+
+use crate::providers::{self, MusicProvider, Song};
+use crate::utils::check_env_existence;
+
+const SESSION_BUS_ENV: &str = "DBUS_SESSION_BUS_ADDRESS";
+const PLAYER_ENV: &str = "MPRIS_PLAYER";
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+#[derive(Default)]
+pub struct Mpris {
+    connection: Option<Connection>,
+}
+
+#[async_trait]
+impl MusicProvider for Mpris {
+    fn name(&self) -> &'static str {
+        "MPRIS"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    fn ratelimit(&self) -> u64 {
+        1
+    }
+
+    async fn connect(&mut self) -> Result<(), providers::Error> {
+        self.connection = Some(Connection::session().await?);
+        Ok(())
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        match &self.connection {
+            Some(connection) => currently_playing(connection).await,
+            None => panic!("Unexpected, no D-Bus connection found"),
+        }
+    }
+}
+
+pub fn verify(exit: bool) -> bool {
+    check_env_existence(SESSION_BUS_ENV, exit)
+}
+
+async fn list_players(connection: &Connection) -> Result<Vec<String>, providers::Error> {
+    let wanted = env::var(PLAYER_ENV).ok().map(|p| p.to_lowercase());
+    let names = DBusProxy::new(connection).await?.list_names().await?;
+
+    Ok(names
+        .into_iter()
+        .map(|name| name.to_string())
+        .filter(|name| name.starts_with(BUS_NAME_PREFIX))
+        .filter(|name| match &wanted {
+            Some(wanted) => name[BUS_NAME_PREFIX.len()..]
+                .to_lowercase()
+                .starts_with(wanted),
+            None => true,
+        })
+        .collect())
+}
+
+async fn player_properties(
+    connection: &Connection,
+    bus_name: String,
+) -> Result<HashMap<String, OwnedValue>, providers::Error> {
+    let proxy = PropertiesProxy::builder(connection)
+        .destination(bus_name)?
+        .path(OBJECT_PATH)?
+        .build()
+        .await?;
+
+    Ok(proxy
+        .get_all(InterfaceName::from_static_str_unchecked(PLAYER_INTERFACE))
+        .await?)
+}
+
+fn get_string(map: &HashMap<String, OwnedValue>, key: &str) -> Option<String> {
+    map.get(key)
+        .and_then(|value| value.try_clone().ok())
+        .and_then(|value| String::try_from(value).ok())
+}
+
+fn get_strings(map: &HashMap<String, OwnedValue>, key: &str) -> Option<Vec<String>> {
+    map.get(key)
+        .and_then(|value| value.try_clone().ok())
+        .and_then(|value| Vec::<String>::try_from(value).ok())
+}
+
+// MPRIS times are in microseconds, either signed or unsigned depending on the player
+fn get_millis(map: &HashMap<String, OwnedValue>, key: &str) -> Option<u64> {
+    let value = map.get(key)?;
+    match (i64::try_from(value), u64::try_from(value)) {
+        (Ok(micros), _) => u64::try_from(micros).ok().map(|micros| micros / 1000),
+        (_, Ok(micros)) => Some(micros / 1000),
+        _ => None,
+    }
+}
+
+fn to_song(
+    properties: &HashMap<String, OwnedValue>,
+    metadata: HashMap<String, OwnedValue>,
+) -> Option<Song> {
+    let status = get_string(properties, "PlaybackStatus").unwrap_or_default();
+    let title = get_string(&metadata, "xesam:title")?;
+
+    Some(Song {
+        playing: status == "Playing",
+        title,
+        artists: get_strings(&metadata, "xesam:artist").unwrap_or_default(),
+        album: get_string(&metadata, "xesam:album").unwrap_or_default(),
+        duration_ms: get_millis(&metadata, "mpris:length"),
+        progress_ms: get_millis(properties, "Position"),
+        url: get_string(&metadata, "xesam:url"),
+        artwork_url: get_string(&metadata, "mpris:artUrl"),
+        id: get_string(&metadata, "xesam:musicBrainzTrackID"),
+        ..Default::default()
+    })
+}
+
+// Prefers an actively playing player over a paused one
+fn select(players: Vec<HashMap<String, OwnedValue>>) -> Option<Song> {
+    let songs = players.into_iter().filter_map(|properties| {
+        let status = get_string(&properties, "PlaybackStatus").unwrap_or_default();
+        if status == "Stopped" {
+            return None;
+        }
+
+        let metadata = match properties.get("Metadata").map(|m| m.try_clone()) {
+            Some(Ok(metadata)) => match HashMap::<String, OwnedValue>::try_from(metadata) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    log::debug!("Couldn't read player metadata: {}", err);
+                    return None;
+                }
+            },
+            _ => return None,
+        };
+        to_song(&properties, metadata)
+    });
+    providers::prefer_playing(songs)
+}
+
+pub async fn currently_playing(connection: &Connection) -> Result<Option<Song>, providers::Error> {
+    let mut players = Vec::new();
+
+    for bus_name in list_players(connection).await? {
+        match player_properties(connection, bus_name.clone()).await {
+            Ok(properties) => players.push(properties),
+            Err(err) => {
+                log::debug!("Couldn't read {} properties: {}", bus_name, err);
+            }
+        }
+    }
+
+    let song = select(players);
+    if song.is_none() {
+        log::debug!("No MPRIS player playing anything");
+    }
+    Ok(song)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::Value;
+
+    fn owned(value: Value) -> OwnedValue {
+        OwnedValue::try_from(value).unwrap()
+    }
+
+    fn metadata(title: &str, length: Value) -> HashMap<String, OwnedValue> {
+        HashMap::from([
+            ("xesam:title".to_string(), owned(Value::from(title))),
+            (
+                "xesam:artist".to_string(),
+                owned(Value::from(vec!["Artist", "Guest"])),
+            ),
+            ("xesam:album".to_string(), owned(Value::from("Album"))),
+            ("mpris:length".to_string(), owned(length)),
+            (
+                "mpris:artUrl".to_string(),
+                owned(Value::from("file:///cover.png")),
+            ),
+        ])
+    }
+
+    fn player(status: &str, title: &str) -> HashMap<String, OwnedValue> {
+        let metadata: HashMap<String, Value> = metadata(title, Value::from(180_000_000i64))
+            .into_iter()
+            .map(|(key, value)| (key, Value::from(value)))
+            .collect();
+        HashMap::from([
+            ("PlaybackStatus".to_string(), owned(Value::from(status))),
+            ("Position".to_string(), owned(Value::from(42_500_000i64))),
+            ("Metadata".to_string(), owned(Value::from(metadata))),
+        ])
+    }
+
+    #[test]
+    fn reads_signed_and_unsigned_lengths() {
+        let signed = metadata("A", Value::from(180_000_000i64));
+        assert_eq!(get_millis(&signed, "mpris:length"), Some(180_000));
+        let unsigned = metadata("A", Value::from(180_000_000u64));
+        assert_eq!(get_millis(&unsigned, "mpris:length"), Some(180_000));
+
+        let negative = metadata("A", Value::from(-1i64));
+        assert_eq!(get_millis(&negative, "mpris:length"), None);
+        let text = metadata("A", Value::from("180"));
+        assert_eq!(get_millis(&text, "mpris:length"), None);
+        assert_eq!(get_millis(&text, "Position"), None);
+    }
+
+    #[test]
+    fn converts_players_to_songs() {
+        let properties = player("Paused", "Title");
+        let song = to_song(&properties, metadata("Title", Value::from(180_000_000u64))).unwrap();
+
+        assert!(!song.playing);
+        assert_eq!(song.title, "Title");
+        assert_eq!(song.artists, vec!["Artist", "Guest"]);
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.duration_ms, Some(180_000));
+        assert_eq!(song.progress_ms, Some(42_500));
+        assert_eq!(song.artwork_url.as_deref(), Some("file:///cover.png"));
+        assert_eq!(song.url, None);
+
+        let mut untitled = metadata("Title", Value::from(0i64));
+        untitled.remove("xesam:title");
+        assert!(to_song(&properties, untitled).is_none());
+    }
+
+    #[test]
+    fn prefers_playing_players() {
+        let song = select(vec![
+            player("Paused", "Paused"),
+            player("Stopped", "Stopped"),
+            player("Playing", "Playing"),
+        ])
+        .unwrap();
+        assert!(song.playing);
+        assert_eq!(song.title, "Playing");
+
+        let song = select(vec![
+            player("Stopped", "Stopped"),
+            player("Paused", "First"),
+            player("Paused", "Second"),
+        ])
+        .unwrap();
+        assert!(!song.playing);
+        assert_eq!(song.title, "First");
+
+        assert!(select(vec![player("Stopped", "Stopped")]).is_none());
+        assert!(select(Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use std::env;
+
+use crate::providers::{self, MusicProvider, Song};
+use crate::utils::check_env_existence;
+
+const URL_ENV: &str = "PLEX_URL";
+const TOKEN_ENV: &str = "PLEX_TOKEN";
+const USERNAME_ENV: &str = "PLEX_USERNAME";
+
+const TOKEN_HEADER: &str = "X-Plex-Token";
+
+#[derive(Deserialize)]
+struct SessionsSchema {
+    #[serde(rename = "MediaContainer")]
+    media_container: MediaContainer,
+}
+
+#[derive(Deserialize)]
+struct MediaContainer {
+    #[serde(rename = "Metadata", default)]
+    metadata: Vec<Metadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    #[serde(rename = "type")]
+    metadata_type: String,
+    title: String,
+    // Track artist, only set when it differs from the album artist
+    original_title: Option<String>,
+    grandparent_title: Option<String>,
+    parent_title: Option<String>,
+    rating_key: Option<String>,
+    duration: Option<u64>,
+    view_offset: Option<u64>,
+    #[serde(rename = "User")]
+    user: Option<User>,
+    #[serde(rename = "Player")]
+    player: Option<Player>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct Player {
+    state: String,
+}
+
+pub struct Plex;
+
+#[async_trait]
+impl MusicProvider for Plex {
+    fn name(&self) -> &'static str {
+        "Plex"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        currently_playing().await
+    }
+}
+
+pub fn verify(exit: bool) -> bool {
+    check_env_existence(URL_ENV, exit);
+    check_env_existence(TOKEN_ENV, exit);
+    check_env_existence(USERNAME_ENV, exit)
+}
+
+fn to_song(metadata: Metadata) -> Song {
+    let playing = match metadata.player {
+        Some(player) => player.state != "paused",
+        None => false,
+    };
+
+    Song {
+        playing,
+        title: metadata.title,
+        artists: metadata
+            .original_title
+            .or(metadata.grandparent_title)
+            .into_iter()
+            .collect(),
+        album: metadata.parent_title.unwrap_or_default(),
+        duration_ms: metadata.duration,
+        progress_ms: metadata.view_offset,
+        id: metadata.rating_key,
+        ..Default::default()
+    }
+}
+
+// A playing session of the user wins over a paused one
+fn select(results: SessionsSchema, username: &str) -> Option<Song> {
+    let songs = results
+        .media_container
+        .metadata
+        .into_iter()
+        .filter(|metadata| {
+            let is_user = match &metadata.user {
+                Some(user) => user.title.eq_ignore_ascii_case(username),
+                None => false,
+            };
+            is_user && metadata.metadata_type == "track"
+        })
+        .map(to_song);
+    providers::prefer_playing(songs)
+}
+
+pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
+    let url = format!(
+        "{}/status/sessions",
+        env::var(URL_ENV).unwrap().trim_end_matches('/')
+    );
+    let token = env::var(TOKEN_ENV).unwrap();
+    let username = env::var(USERNAME_ENV).unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(TOKEN_HEADER, token.parse().unwrap());
+    headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+
+    let client = reqwest::Client::new();
+    let response = client.get(url).headers(headers).send().await?;
+
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Fatal,
+                message: "Incorrect Plex token".to_string(),
+            });
+        }
+        reqwest::StatusCode::TOO_MANY_REQUESTS => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Ratelimit,
+                message: "Too many requests".to_string(),
+            });
+        }
+        reqwest::StatusCode::OK => {}
+        status_code => {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Unknown,
+                message: format!("Unhandled status code {} from Plex", status_code),
+            });
+        }
+    }
+
+    let results = response.json::<SessionsSchema>().await?;
+
+    let currently_playing = select(results, &username);
+    if currently_playing.is_none() {
+        log::debug!("No track session for {}", username);
+    }
+    Ok(currently_playing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed down `/status/sessions` response
+    const SESSIONS: &str = r#"{
+        "MediaContainer": {
+            "size": 5,
+            "Metadata": [
+                {
+                    "type": "track",
+                    "title": "Not mine",
+                    "User": {"title": "other"},
+                    "Player": {"state": "playing"}
+                },
+                {
+                    "type": "episode",
+                    "title": "A show",
+                    "User": {"title": "Hugo"},
+                    "Player": {"state": "playing"}
+                },
+                {
+                    "type": "track",
+                    "title": "Paused song",
+                    "grandparentTitle": "Album Artist",
+                    "parentTitle": "Album",
+                    "ratingKey": "12",
+                    "duration": 180000,
+                    "viewOffset": 42500,
+                    "User": {"title": "hugo"},
+                    "Player": {"state": "paused"}
+                },
+                {
+                    "type": "track",
+                    "title": "Playing song",
+                    "originalTitle": "Track Artist",
+                    "grandparentTitle": "Album Artist",
+                    "User": {"title": "hugo"},
+                    "Player": {"state": "playing"}
+                }
+            ]
+        }
+    }"#;
+
+    fn sessions() -> SessionsSchema {
+        serde_json::from_str(SESSIONS).unwrap()
+    }
+
+    #[test]
+    fn playing_session_wins() {
+        let song = select(sessions(), "hugo").unwrap();
+        assert!(song.playing);
+        assert_eq!(song.title, "Playing song");
+        assert_eq!(song.artists, vec!["Track Artist"]);
+        assert_eq!(song.album, "");
+    }
+
+    #[test]
+    fn falls_back_to_a_paused_session() {
+        let mut sessions = sessions();
+        sessions.media_container.metadata.pop();
+
+        let song = select(sessions, "HUGO").unwrap();
+        assert!(!song.playing);
+        assert_eq!(song.title, "Paused song");
+        assert_eq!(song.artists, vec!["Album Artist"]);
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.id.as_deref(), Some("12"));
+        assert_eq!(song.duration_ms, Some(180_000));
+        assert_eq!(song.progress_ms, Some(42_500));
+    }
+
+    #[test]
+    fn ignores_other_users_and_media() {
+        assert!(select(sessions(), "nobody").is_none());
+
+        let mut sessions = sessions();
+        sessions.media_container.metadata.truncate(2);
+        assert!(select(sessions, "hugo").is_none());
+
+        let empty: SessionsSchema =
+            serde_json::from_str(r#"{"MediaContainer": {"size": 0}}"#).unwrap();
+        assert!(select(empty, "hugo").is_none());
+    }
+}
@@ -14,7 +14,7 @@ use crate::{
     commands::connect::get_server_info,
     database,
     providers::{
-        self, PlatformParameters,
+        self,
         spotify::{CLIENT_ID_ENV, CLIENT_SECRET_ENV},
     },
 };
@@ -24,8 +24,8 @@ const ACCESS_TOKEN_API_LINK: &str = "https://accounts.spotify.com/api/token";
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AccessTokenJson {
-    access_token: String,
-    refresh_token: String,
+    pub access_token: String,
+    pub refresh_token: String,
 }
 
 // TODO: not a huge fan of this solution but it works for now lol
@@ -117,29 +117,20 @@ async fn get_refresh_token(refresh_token: String) -> Result<AccessTokenJson, pro
     let json = resp.json::<RefreshTokenJson>().await?;
     let creds = AccessTokenJson {
         access_token: json.access_token,
-        refresh_token: refresh_token,
+        refresh_token,
     };
     database::spotify::set_creds(creds.clone());
     Ok(creds)
 }
 
-pub async fn refresh(
-    parameters: Option<PlatformParameters>,
-) -> Result<Option<PlatformParameters>, providers::Error> {
-    if parameters.is_none() {
-        return Err(providers::Error {
+pub async fn refresh(creds: Option<AccessTokenJson>) -> Result<AccessTokenJson, providers::Error> {
+    match creds {
+        Some(creds) => get_refresh_token(creds.refresh_token).await,
+        None => Err(providers::Error {
             error_type: providers::ErrorType::Unknown,
-            message: "No parameters provided".to_string(),
-        });
+            message: "No credentials provided".to_string(),
+        }),
     }
-
-    let mut new_params = parameters.clone().unwrap();
-    let json = get_refresh_token(new_params.spotify_refresh_token.unwrap()).await?;
-
-    new_params.spotify_access_token = Some(json.access_token);
-    new_params.spotify_refresh_token = Some(json.refresh_token);
-
-    Ok(Some(new_params))
 }
 
 #[derive(Deserialize)]
@@ -234,25 +225,21 @@ pub async fn login_server() -> Result<AccessTokenJson, providers::Error> {
         });
     }
 
-    let creds = get_access_token(query_state.code.lock().unwrap().clone(), redirect_uri).await?;
+    let code = query_state.code.lock().unwrap().clone();
+    let creds = get_access_token(code, redirect_uri).await?;
     Ok(creds)
 }
 
-pub async fn connect() -> Result<Option<PlatformParameters>, providers::Error> {
-    let mut params = PlatformParameters::default();
-    let creds: AccessTokenJson = match database::spotify::get_creds() {
-        Some(db_creds) => db_creds,
+pub async fn connect() -> Result<AccessTokenJson, providers::Error> {
+    match database::spotify::get_creds() {
+        Some(db_creds) => Ok(db_creds),
         None => {
             log::error!(
                 "Couldn't find Spotify credentials, please use `imaginal connect` and try again."
             );
             process::exit(1);
         }
-    };
-
-    params.spotify_access_token = Some(creds.access_token);
-    params.spotify_refresh_token = Some(creds.refresh_token);
-    Ok(Some(params))
+    }
 }
 
 #[derive(Default)]
@@ -268,12 +255,7 @@ impl StopHandle {
 
     /// Sends stop signal through contained server handle.
     pub(crate) async fn stop(&self, graceful: bool) {
-        self.inner
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .stop(graceful)
-            .await
+        let handle = self.inner.lock().unwrap().clone().unwrap();
+        handle.stop(graceful).await
     }
 }
@@ -1,4 +1,10 @@
-use crate::utils::check_env_existence;
+use async_trait::async_trait;
+
+use crate::{
+    database,
+    providers::{self, MusicProvider, Song},
+    utils::check_env_existence,
+};
 
 pub mod connection;
 pub mod playing;
@@ -10,3 +16,46 @@ pub fn verify(panic: bool) -> bool {
     check_env_existence(CLIENT_ID_ENV, panic);
     check_env_existence(CLIENT_SECRET_ENV, panic)
 }
+
+#[derive(Default)]
+pub struct Spotify {
+    creds: Option<connection::AccessTokenJson>,
+}
+
+#[async_trait]
+impl MusicProvider for Spotify {
+    fn name(&self) -> &'static str {
+        "Spotify"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn connect(&mut self) -> Result<(), providers::Error> {
+        self.creds = Some(connection::connect().await?);
+        Ok(())
+    }
+
+    async fn refresh(&mut self) -> Result<(), providers::Error> {
+        self.creds = Some(connection::refresh(self.creds.clone()).await?);
+        Ok(())
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        playing::currently_playing(self.creds.clone()).await
+    }
+
+    async fn login_server(&self) -> Result<bool, providers::Error> {
+        let creds = connection::login_server().await?;
+        log::debug!("Saving credentials to database");
+        // `set_creds` already logged why
+        if !database::spotify::set_creds(creds) {
+            return Err(providers::Error {
+                error_type: providers::ErrorType::Unknown,
+                message: "Couldn't save the Spotify credentials".to_string(),
+            });
+        }
+        Ok(true)
+    }
+}
@@ -1,26 +1,70 @@
 use reqwest::header::HeaderMap;
 use serde::Deserialize;
 
-use crate::providers::{self, PlatformParameters, Song};
+use crate::providers::{self, Song, SongKind, spotify::connection::AccessTokenJson};
 
 const CURRENTLY_PLAYING_API_LINK: &str = "https://api.spotify.com/v1/me/player/currently-playing";
 
 #[derive(Deserialize)]
 struct CurrentlyPlayingSchema {
     is_playing: bool,
-    item: Item,
+    progress_ms: Option<u64>,
+    currently_playing_type: String,
+    // null during ads
+    item: Option<Item>,
 }
 
 #[derive(Deserialize)]
-struct Item {
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Item {
+    Track(Track),
+    Episode(Episode),
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+struct Track {
     album: Album,
     artists: Vec<Artist>,
     name: String,
+    duration_ms: u64,
+    external_urls: ExternalUrls,
+    // Local files don't have any
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Episode {
+    name: String,
+    duration_ms: u64,
+    external_urls: ExternalUrls,
+    id: String,
+    images: Vec<Image>,
+    show: Show,
+}
+
+#[derive(Deserialize)]
+struct Show {
+    name: String,
+    publisher: String,
 }
 
 #[derive(Deserialize)]
 struct Album {
     name: String,
+    images: Vec<Image>,
+}
+
+#[derive(Deserialize)]
+struct ExternalUrls {
+    spotify: Option<String>,
+}
+
+// Images are sorted widest first
+#[derive(Deserialize)]
+struct Image {
+    url: String,
 }
 
 #[derive(Deserialize)]
@@ -29,27 +73,25 @@ struct Artist {
 }
 
 pub async fn currently_playing(
-    parameters: Option<PlatformParameters>,
+    creds: Option<AccessTokenJson>,
 ) -> Result<Option<Song>, providers::Error> {
-    if parameters.is_none() {
-        panic!("Unexpected, no parameters found");
+    if creds.is_none() {
+        panic!("Unexpected, no credentials found");
     }
 
     let mut headers = HeaderMap::new();
 
     headers.insert(
         reqwest::header::AUTHORIZATION,
-        format!(
-            "Bearer {}",
-            parameters.unwrap().spotify_access_token.unwrap()
-        )
-        .parse()
-        .unwrap(),
+        format!("Bearer {}", creds.unwrap().access_token)
+            .parse()
+            .unwrap(),
     );
 
     let client = reqwest::Client::new();
     let response = client
         .get(CURRENTLY_PLAYING_API_LINK)
+        .query(&[("additional_types", "track,episode")])
         .headers(headers)
         .send()
         .await?;
@@ -75,17 +117,145 @@ pub async fn currently_playing(
 
     let results = response.json::<CurrentlyPlayingSchema>().await?;
 
-    let artist_name: String = match results.item.artists.into_iter().next() {
-        Some(artist) => artist.name,
-        None => String::from("Unknown artist"),
-    };
+    Ok(to_song(results))
+}
 
-    let currently_playing: Option<Song> = Some(Song {
-        playing: results.is_playing,
-        title: results.item.name,
-        artist: artist_name,
-        album: results.item.album.name,
-    });
+fn to_song(results: CurrentlyPlayingSchema) -> Option<Song> {
+    match results.item {
+        Some(Item::Track(track)) => Some(Song {
+            playing: results.is_playing,
+            title: track.name,
+            artists: track
+                .artists
+                .into_iter()
+                .map(|artist| artist.name)
+                .collect(),
+            album: track.album.name,
+            duration_ms: Some(track.duration_ms),
+            progress_ms: results.progress_ms,
+            url: track.external_urls.spotify,
+            artwork_url: first_image(track.album.images),
+            id: track.id,
+            ..Default::default()
+        }),
+        Some(Item::Episode(episode)) => Some(Song {
+            kind: SongKind::Episode,
+            playing: results.is_playing,
+            title: episode.name,
+            duration_ms: Some(episode.duration_ms),
+            progress_ms: results.progress_ms,
+            url: episode.external_urls.spotify,
+            artwork_url: first_image(episode.images),
+            id: Some(episode.id),
+            show: Some(episode.show.name),
+            publisher: Some(episode.show.publisher),
+            ..Default::default()
+        }),
+        // Spotify doesn't give any detail about ads or unknown items
+        _ => {
+            let kind = match results.currently_playing_type.as_str() {
+                "ad" => SongKind::Ad,
+                "unknown" => SongKind::Unknown,
+                // No item during private sessions and while a track loads
+                playing_type => {
+                    log::debug!("No {} item currently playing", playing_type);
+                    return None;
+                }
+            };
+            Some(Song {
+                kind,
+                playing: results.is_playing,
+                progress_ms: results.progress_ms,
+                ..Default::default()
+            })
+        }
+    }
+}
+
+fn first_image(images: Vec<Image>) -> Option<String> {
+    images.into_iter().next().map(|image| image.url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Option<Song> {
+        to_song(serde_json::from_str(json).unwrap())
+    }
 
-    Ok(currently_playing)
+    fn without_item(playing_type: &str) -> String {
+        format!(
+            r#"{{ "is_playing": true, "progress_ms": 0, "currently_playing_type": "{}", "item": null }}"#,
+            playing_type
+        )
+    }
+
+    #[test]
+    fn track() {
+        let song = parse(
+            r#"{
+                "is_playing": true,
+                "progress_ms": 1000,
+                "currently_playing_type": "track",
+                "item": {
+                    "type": "track",
+                    "name": "Song",
+                    "duration_ms": 200000,
+                    "id": "abc",
+                    "external_urls": { "spotify": "https://open.spotify.com/track/abc" },
+                    "artists": [{ "name": "First" }, { "name": "Second" }],
+                    "album": { "name": "Album", "images": [{ "url": "big" }, { "url": "small" }] }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(song.kind, SongKind::Track);
+        assert_eq!(song.title, "Song");
+        assert_eq!(song.artists, vec!["First", "Second"]);
+        assert_eq!(song.artwork_url.as_deref(), Some("big"));
+        assert_eq!(song.progress_ms, Some(1000));
+    }
+
+    #[test]
+    fn episode() {
+        let song = parse(
+            r#"{
+                "is_playing": false,
+                "progress_ms": null,
+                "currently_playing_type": "episode",
+                "item": {
+                    "type": "episode",
+                    "name": "Episode",
+                    "duration_ms": 3600000,
+                    "id": "def",
+                    "external_urls": {},
+                    "images": [],
+                    "show": { "name": "Show", "publisher": "Publisher" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(song.kind, SongKind::Episode);
+        assert!(!song.playing);
+        assert_eq!(song.show.as_deref(), Some("Show"));
+        assert_eq!(song.url, None);
+    }
+
+    #[test]
+    fn ad_and_unknown_without_item() {
+        assert_eq!(parse(&without_item("ad")).unwrap().kind, SongKind::Ad);
+        assert_eq!(
+            parse(&without_item("unknown")).unwrap().kind,
+            SongKind::Unknown
+        );
+    }
+
+    #[test]
+    fn missing_item_is_nothing_playing() {
+        assert_eq!(parse(&without_item("track")), None);
+        assert_eq!(parse(&without_item("episode")), None);
+    }
 }
@@ -0,0 +1,227 @@
+use async_trait::async_trait;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::Deserialize;
+use std::env;
+
+use crate::providers::{self, MusicProvider, Song};
+use crate::utils::check_env_existence;
+
+const URL_ENV: &str = "SUBSONIC_URL";
+const USERNAME_ENV: &str = "SUBSONIC_USERNAME";
+const PASSWORD_ENV: &str = "SUBSONIC_PASSWORD";
+
+const API_VERSION: &str = "1.16.1";
+const CLIENT_NAME: &str = "imaginal";
+
+#[derive(Deserialize)]
+struct NowPlayingSchema {
+    #[serde(rename = "subsonic-response")]
+    subsonic_response: SubsonicResponse,
+}
+
+#[derive(Deserialize)]
+struct SubsonicResponse {
+    status: String,
+    error: Option<Error>,
+    #[serde(rename = "nowPlaying")]
+    now_playing: Option<NowPlaying>,
+}
+
+#[derive(Deserialize)]
+struct Error {
+    code: u16,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NowPlaying {
+    #[serde(default)]
+    entry: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    id: String,
+    title: String,
+    // In seconds
+    duration: Option<u64>,
+    artist: Option<String>,
+    album: Option<String>,
+    username: String,
+    #[serde(rename = "minutesAgo", default)]
+    minutes_ago: u64,
+}
+
+pub struct Subsonic;
+
+#[async_trait]
+impl MusicProvider for Subsonic {
+    fn name(&self) -> &'static str {
+        "Subsonic"
+    }
+
+    fn verify(&self, exit: bool) -> bool {
+        verify(exit)
+    }
+
+    async fn currently_playing(&self) -> Result<Option<Song>, providers::Error> {
+        currently_playing().await
+    }
+}
+
+pub fn verify(exit: bool) -> bool {
+    check_env_existence(URL_ENV, exit);
+    check_env_existence(USERNAME_ENV, exit);
+    check_env_existence(PASSWORD_ENV, exit)
+}
+
+// Every user's players are listed, keep the most recent one of ours
+fn select(now_playing: Option<NowPlaying>, username: &str) -> Option<Song> {
+    let entries = match now_playing {
+        Some(now_playing) => now_playing.entry,
+        None => vec![],
+    };
+    entries
+        .into_iter()
+        .filter(|entry| entry.username == username)
+        .min_by_key(|entry| entry.minutes_ago)
+        .map(|entry| Song {
+            playing: true,
+            title: entry.title,
+            artists: entry.artist.into_iter().collect(),
+            album: entry.album.unwrap_or_default(),
+            duration_ms: entry.duration.map(|secs| secs * 1000),
+            id: Some(entry.id),
+            ..Default::default()
+        })
+}
+
+pub async fn currently_playing() -> Result<Option<Song>, providers::Error> {
+    let url = format!(
+        "{}/rest/getNowPlaying",
+        env::var(URL_ENV).unwrap().trim_end_matches('/')
+    );
+    let username = env::var(USERNAME_ENV).unwrap();
+    let password = env::var(PASSWORD_ENV).unwrap();
+
+    // https://www.subsonic.org/pages/api.jsp, token is md5(password + salt)
+    let salt = Alphanumeric.sample_string(&mut rand::rng(), 12);
+    let token = format!("{:x}", md5::compute(format!("{}{}", password, salt)));
+
+    let query = [
+        ("u", username.as_str()),
+        ("t", token.as_str()),
+        ("s", salt.as_str()),
+        ("v", API_VERSION),
+        ("c", CLIENT_NAME),
+        ("f", "json"),
+    ];
+
+    let client = reqwest::Client::new();
+    let response = client.get(url).query(&query).send().await?;
+
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(providers::Error {
+            error_type: providers::ErrorType::Ratelimit,
+            message: "Too many requests".to_string(),
+        });
+    }
+
+    let results = response.json::<NowPlayingSchema>().await?.subsonic_response;
+
+    if results.status != "ok" {
+        let error = results.error.unwrap_or(Error {
+            code: 0,
+            message: None,
+        });
+        match error.code {
+            40 => {
+                return Err(providers::Error {
+                    error_type: providers::ErrorType::Fatal,
+                    message: "Wrong username or password".to_string(),
+                });
+            }
+            41 => {
+                return Err(providers::Error {
+                    error_type: providers::ErrorType::Fatal,
+                    message: "Server doesn't support token authentication".to_string(),
+                });
+            }
+            _ => {}
+        }
+
+        return Err(providers::Error {
+            error_type: providers::ErrorType::Unknown,
+            message: error
+                .message
+                .unwrap_or("Unhandled request error coming from Subsonic".to_string()),
+        });
+    }
+
+    let currently_playing = select(results.now_playing, &username);
+    if currently_playing.is_none() {
+        log::debug!("Nothing playing for {}", username);
+    }
+    Ok(currently_playing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed down `getNowPlaying` response
+    const NOW_PLAYING: &str = r#"{
+        "subsonic-response": {
+            "status": "ok",
+            "version": "1.16.1",
+            "nowPlaying": {
+                "entry": [
+                    {"id": "1", "title": "Older", "username": "hugo", "minutesAgo": 12},
+                    {"id": "2", "title": "Someone else's", "username": "other", "minutesAgo": 0},
+                    {
+                        "id": "3",
+                        "title": "Latest",
+                        "artist": "Artist",
+                        "album": "Album",
+                        "duration": 180,
+                        "username": "hugo",
+                        "minutesAgo": 1
+                    }
+                ]
+            }
+        }
+    }"#;
+
+    fn now_playing(json: &str) -> Option<NowPlaying> {
+        serde_json::from_str::<NowPlayingSchema>(json)
+            .unwrap()
+            .subsonic_response
+            .now_playing
+    }
+
+    #[test]
+    fn keeps_the_most_recent_entry_of_the_user() {
+        let song = select(now_playing(NOW_PLAYING), "hugo").unwrap();
+        assert!(song.playing);
+        assert_eq!(song.title, "Latest");
+        assert_eq!(song.artists, vec!["Artist"]);
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.duration_ms, Some(180_000));
+        assert_eq!(song.id.as_deref(), Some("3"));
+
+        let song = select(now_playing(NOW_PLAYING), "other").unwrap();
+        assert_eq!(song.title, "Someone else's");
+        assert!(song.artists.is_empty());
+        assert_eq!(song.duration_ms, None);
+    }
+
+    #[test]
+    fn nothing_playing() {
+        assert!(select(now_playing(NOW_PLAYING), "nobody").is_none());
+
+        let empty = r#"{"subsonic-response": {"status": "ok", "nowPlaying": {}}}"#;
+        assert!(select(now_playing(empty), "hugo").is_none());
+        let missing = r#"{"subsonic-response": {"status": "ok"}}"#;
+        assert!(select(now_playing(missing), "hugo").is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::providers::Update;
+
+mod discord;
+mod files;
+mod hooks;
+mod mqtt;
+mod notifications;
+mod webhooks;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Somewhere updates are sent to, on top of the standard output.
+///
+/// Sinks are enabled by their environment variables, see [`Sinks::from_env`].
+#[async_trait]
+pub trait Sink: Send {
+    /// Name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Only called for updates with an event.
+    async fn write(&mut self, update: &Update) -> Result<(), Error>;
+}
+
+pub struct Sinks {
+    senders: Vec<UnboundedSender<Update>>,
+}
+
+// Each sink writes from its own task, so a slow one never holds back polling
+// or the other sinks, while still getting its updates in order
+fn spawn(mut sink: Box<dyn Sink>) -> UnboundedSender<Update> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Update>();
+    tokio::spawn(async move {
+        while let Some(update) = receiver.recv().await {
+            // A broken sink shouldn't stop the other ones
+            if let Err(err) = sink.write(&update).await {
+                log::error!("{} sink: {}", sink.name(), err);
+            }
+        }
+    });
+    sender
+}
+
+impl Sinks {
+    /// Every sink whose environment is set.
+    ///
+    /// In-house sinks only need to be added here.
+    pub fn from_env() -> Self {
+        let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+        if files::enabled() {
+            sinks.push(Box::new(files::Files::from_env()));
+        }
+        if discord::enabled() {
+            sinks.push(Box::new(discord::Discord::from_env()));
+        }
+        if notifications::enabled() {
+            sinks.push(Box::new(notifications::Notifications::from_env()));
+        }
+        if hooks::enabled() {
+            sinks.push(Box::new(hooks::Hooks::from_env()));
+        }
+        if webhooks::enabled() {
+            sinks.push(Box::new(webhooks::Webhooks::from_env()));
+        }
+        if mqtt::enabled() {
+            sinks.push(Box::new(mqtt::Mqtt::from_env()));
+        }
+
+        let senders = sinks
+            .into_iter()
+            .map(|sink| {
+                log::info!("Using sink {}", sink.name());
+                spawn(sink)
+            })
+            .collect();
+        Sinks { senders }
+    }
+
+    /// Hands the update over to every sink without waiting for them.
+    pub fn write(&self, update: &Update) {
+        if update.event.is_none() {
+            return;
+        }
+
+        for sender in &self.senders {
+            // Only fails once the runtime is shutting down
+            let _ = sender.send(update.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{Event, State};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    struct Slow {
+        written: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl Sink for Slow {
+        fn name(&self) -> &'static str {
+            "Slow"
+        }
+
+        async fn write(&mut self, update: &Update) -> Result<(), Error> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            self.written.lock().unwrap().push(update.timestamp);
+            Ok(())
+        }
+    }
+
+    fn update(timestamp: u64, event: Option<Event>) -> Update {
+        Update {
+            platform: "Test",
+            timestamp,
+            state: State::Idle,
+            event,
+            song: None,
+            artist: None,
+            error: None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_sinks_dont_block_and_keep_order() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sinks = Sinks {
+            senders: vec![spawn(Box::new(Slow {
+                written: written.clone(),
+            }))],
+        };
+
+        let start = tokio::time::Instant::now();
+        sinks.write(&update(1, Some(Event::Idle)));
+        sinks.write(&update(2, None));
+        sinks.write(&update(3, Some(Event::Heartbeat)));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        tokio::time::sleep(Duration::from_secs(25)).await;
+        assert_eq!(*written.lock().unwrap(), vec![1, 3]);
+    }
+}
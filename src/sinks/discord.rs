@@ -0,0 +1,418 @@
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+    process, time,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+};
+
+use crate::{
+    providers::{Song, SongKind, State, Update},
+    sinks::{Error, Sink},
+    utils::check_env_existence,
+};
+
+const CLIENT_ID_ENV: &str = "DISCORD_CLIENT_ID";
+
+// https://github.com/discord/discord-rpc/blob/master/documentation/hard-mode.md
+const OP_HANDSHAKE: u32 = 0;
+const OP_FRAME: u32 = 1;
+const OP_CLOSE: u32 = 2;
+const RPC_VERSION: u32 = 1;
+const SOCKETS: u32 = 10;
+// A stuck client mustn't hold back the following updates
+const IPC_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+// https://discord.com/developers/docs/events/gateway-events#activity-object-activity-types
+const ACTIVITY_LISTENING: u8 = 2;
+// Shorter or longer strings make Discord reject the whole activity
+const MIN_TEXT_LEN: usize = 2;
+const MAX_TEXT_LEN: usize = 128;
+// Blank but not whitespace, so Discord doesn't trim it away
+const PADDING: char = '\u{2800}';
+
+pub fn enabled() -> bool {
+    check_env_existence(CLIENT_ID_ENV, false)
+}
+
+/// "Listening to" Rich Presence, through the local Discord client.
+pub struct Discord {
+    client_id: String,
+    /// Where the client sockets are
+    runtime_dir: PathBuf,
+    socket: Option<UnixStream>,
+    nonce: u64,
+}
+
+fn runtime_dir() -> PathBuf {
+    let dir = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .find_map(|var| env::var(var).ok())
+        .unwrap_or("/tmp".to_string());
+    PathBuf::from(dir)
+}
+
+// Sandboxed clients put their socket in a subfolder
+fn socket_paths(dir: &Path) -> Vec<PathBuf> {
+    ["", "app/com.discordapp.Discord", "snap.discord"]
+        .iter()
+        .flat_map(|subfolder| {
+            let dir = dir.join(subfolder);
+            (0..SOCKETS).map(move |i| dir.join(format!("discord-ipc-{}", i)))
+        })
+        .collect()
+}
+
+async fn write_frame(socket: &mut UnixStream, opcode: u32, payload: &Value) -> io::Result<()> {
+    let payload = payload.to_string();
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&opcode.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload.as_bytes());
+    socket.write_all(&frame).await
+}
+
+async fn read_frame(socket: &mut UnixStream) -> io::Result<(u32, Value)> {
+    let mut header = [0; 8];
+    socket.read_exact(&mut header).await?;
+    let opcode = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let length = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    let mut payload = vec![0; length as usize];
+    socket.read_exact(&mut payload).await?;
+    Ok((opcode, serde_json::from_slice(&payload)?))
+}
+
+fn fit(text: &str) -> String {
+    let mut text: String = text.chars().take(MAX_TEXT_LEN).collect();
+    while text.chars().count() < MIN_TEXT_LEN {
+        text.push(PADDING);
+    }
+    text
+}
+
+fn unix_millis() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn activity(song: &Song) -> Value {
+    let (state, large_text) = match song.kind {
+        SongKind::Episode => (song.show.clone(), song.publisher.clone()),
+        _ => (Some(song.artist()), Some(song.album.clone())),
+    };
+
+    let mut activity = json!({
+        "type": ACTIVITY_LISTENING,
+        "details": fit(&song.title),
+    });
+    if let Some(state) = state.filter(|state| !state.is_empty()) {
+        activity["state"] = json!(fit(&state));
+    }
+
+    // Discord fetches the external image itself, local files can't be shown
+    let mut assets = json!({});
+    if let Some(url) = song
+        .artwork_url
+        .as_ref()
+        .filter(|url| url.starts_with("http"))
+    {
+        assets["large_image"] = json!(url);
+    }
+    if let Some(large_text) = large_text.filter(|text| !text.is_empty()) {
+        assets["large_text"] = json!(fit(&large_text));
+    }
+    if assets != json!({}) {
+        activity["assets"] = assets;
+    }
+
+    // Discord shows the elapsed and remaining time from these
+    if let Some(progress) = song.progress_ms {
+        let start = unix_millis().saturating_sub(progress);
+        activity["timestamps"] = match song.duration_ms {
+            Some(duration) => json!({ "start": start, "end": start + duration }),
+            None => json!({ "start": start }),
+        };
+    }
+    activity
+}
+
+impl Discord {
+    pub fn from_env() -> Self {
+        Discord {
+            client_id: env::var(CLIENT_ID_ENV).unwrap(),
+            runtime_dir: runtime_dir(),
+            socket: None,
+            nonce: 0,
+        }
+    }
+
+    async fn connect(&self) -> Result<UnixStream, Error> {
+        for path in socket_paths(&self.runtime_dir) {
+            let mut socket = match UnixStream::connect(&path).await {
+                Ok(socket) => socket,
+                Err(_) => continue,
+            };
+            log::debug!("Connecting to Discord through {}", path.display());
+
+            let handshake = json!({ "v": RPC_VERSION, "client_id": self.client_id });
+            let response = tokio::time::timeout(IPC_TIMEOUT, async {
+                write_frame(&mut socket, OP_HANDSHAKE, &handshake).await?;
+                read_frame(&mut socket).await
+            })
+            .await;
+            match response {
+                Ok(Ok((OP_FRAME, payload))) if payload["evt"] == "READY" => return Ok(socket),
+                Ok(Ok((_, payload))) => {
+                    return Err(format!("Discord refused the handshake: {}", payload).into());
+                }
+                Ok(Err(err)) => return Err(err.into()),
+                Err(_) => return Err("Discord didn't answer the handshake".into()),
+            }
+        }
+        Err("Couldn't find a running Discord client".into())
+    }
+
+    async fn set_activity(&mut self, activity: Option<Value>) -> Result<(), Error> {
+        // Nothing shown yet, nothing to clear
+        if activity.is_none() && self.socket.is_none() {
+            return Ok(());
+        }
+        let socket = match self.socket.as_mut() {
+            Some(socket) => socket,
+            None => self.socket.insert(self.connect().await?),
+        };
+
+        self.nonce += 1;
+        let payload = json!({
+            "cmd": "SET_ACTIVITY",
+            "args": { "pid": process::id(), "activity": activity },
+            "nonce": self.nonce.to_string(),
+        });
+
+        let response = tokio::time::timeout(IPC_TIMEOUT, async {
+            write_frame(socket, OP_FRAME, &payload).await?;
+            read_frame(socket).await
+        })
+        .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(_) => {
+                self.socket = None;
+                return Err("Discord didn't answer in time".into());
+            }
+        };
+        match response {
+            Ok((OP_FRAME, response)) if response["evt"] == "ERROR" => {
+                Err(format!("Discord rejected the activity: {}", response["data"]).into())
+            }
+            Ok((OP_FRAME, _)) => Ok(()),
+            Ok((OP_CLOSE, response)) => {
+                self.socket = None;
+                Err(format!("Discord closed the connection: {}", response["message"]).into())
+            }
+            // Discord was closed or restarted, reconnect on the next update
+            _ => {
+                self.socket = None;
+                Err("Lost the connection to Discord".into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for Discord {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    async fn write(&mut self, update: &Update) -> Result<(), Error> {
+        match (update.state, &update.song) {
+            // Keep the activity until the provider comes back
+            (State::Error, _) => Ok(()),
+            (_, Some(song)) if song.playing => self.set_activity(Some(activity(song))).await,
+            // Paused and idle, nothing to listen to
+            _ => self.set_activity(None).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::Event;
+    use std::fs;
+    use tokio::{net::UnixListener, sync::mpsc};
+
+    fn track(playing: bool) -> Song {
+        Song {
+            kind: SongKind::Track,
+            playing,
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string()],
+            album: "Album".to_string(),
+            duration_ms: Some(180_000),
+            progress_ms: Some(42_000),
+            artwork_url: Some("https://example.com/cover.jpg".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn update(state: State, song: Option<Song>) -> Update {
+        Update {
+            platform: "Test",
+            timestamp: 0,
+            state,
+            event: Some(Event::TrackChange),
+            song,
+            artist: None,
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        write_frame(&mut client, OP_FRAME, &json!({ "cmd": "PING" }))
+            .await
+            .unwrap();
+
+        let mut header = [0; 8];
+        server.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, [1, 0, 0, 0, 14, 0, 0, 0]);
+        let mut payload = [0; 14];
+        server.read_exact(&mut payload).await.unwrap();
+        assert_eq!(&payload, br#"{"cmd":"PING"}"#);
+
+        write_frame(&mut server, OP_CLOSE, &json!({ "code": 4000 }))
+            .await
+            .unwrap();
+        let (opcode, payload) = read_frame(&mut client).await.unwrap();
+        assert_eq!(opcode, OP_CLOSE);
+        assert_eq!(payload["code"], 4000);
+    }
+
+    #[test]
+    fn fits_discord_text_limits() {
+        assert_eq!(fit(""), "\u{2800}\u{2800}");
+        assert_eq!(fit("a"), "a\u{2800}");
+        assert_eq!(fit("ab"), "ab");
+        let long = "é".repeat(200);
+        assert_eq!(fit(&long), "é".repeat(MAX_TEXT_LEN));
+    }
+
+    #[test]
+    fn builds_listening_activities() {
+        let before = unix_millis();
+        let activity = activity(&track(true));
+        let after = unix_millis();
+
+        assert_eq!(activity["type"], ACTIVITY_LISTENING);
+        assert_eq!(activity["details"], "Title");
+        assert_eq!(activity["state"], "Artist");
+        assert_eq!(
+            activity["assets"]["large_image"],
+            "https://example.com/cover.jpg"
+        );
+        assert_eq!(activity["assets"]["large_text"], "Album");
+
+        let start = activity["timestamps"]["start"].as_u64().unwrap();
+        let end = activity["timestamps"]["end"].as_u64().unwrap();
+        assert!((before - 42_000..=after - 42_000).contains(&start));
+        assert_eq!(end - start, 180_000);
+    }
+
+    #[test]
+    fn skips_what_discord_cant_show() {
+        let episode = Song {
+            kind: SongKind::Episode,
+            show: Some("Show".to_string()),
+            publisher: Some("Publisher".to_string()),
+            artwork_url: Some("file:///cover.jpg".to_string()),
+            duration_ms: None,
+            ..track(true)
+        };
+        let shown = activity(&episode);
+        assert_eq!(shown["state"], "Show");
+        assert_eq!(shown["assets"], json!({ "large_text": "Publisher" }));
+        assert_eq!(shown["timestamps"].as_object().unwrap().len(), 1);
+
+        let bare = Song {
+            title: "Title".to_string(),
+            album: String::new(),
+            ..Default::default()
+        };
+        let shown = activity(&bare);
+        assert!(shown.get("assets").is_none());
+        assert!(shown.get("timestamps").is_none());
+    }
+
+    /// Fake Discord client answering on `discord-ipc-0`, forwarding every frame it gets.
+    fn client(dir: &Path) -> mpsc::UnboundedReceiver<(u32, Value)> {
+        let listener = UnixListener::bind(dir.join("discord-ipc-0")).unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                while let Ok((opcode, payload)) = read_frame(&mut socket).await {
+                    let response = match opcode {
+                        OP_HANDSHAKE => json!({ "cmd": "DISPATCH", "evt": "READY" }),
+                        _ => {
+                            json!({ "cmd": "SET_ACTIVITY", "evt": null, "nonce": payload["nonce"] })
+                        }
+                    };
+                    write_frame(&mut socket, OP_FRAME, &response).await.unwrap();
+                    sender.send((opcode, payload)).unwrap();
+                }
+            }
+        });
+        receiver
+    }
+
+    #[tokio::test]
+    async fn sets_and_clears_the_activity() {
+        let dir = env::temp_dir().join(format!("imaginal-discord-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut frames = client(&dir);
+        let mut discord = Discord {
+            client_id: "1234".to_string(),
+            runtime_dir: dir.clone(),
+            socket: None,
+            nonce: 0,
+        };
+
+        // Nothing shown yet, no need to connect
+        discord.write(&update(State::Idle, None)).await.unwrap();
+        assert!(discord.socket.is_none());
+
+        discord
+            .write(&update(State::Playing, Some(track(true))))
+            .await
+            .unwrap();
+        let (opcode, handshake) = frames.recv().await.unwrap();
+        assert_eq!(opcode, OP_HANDSHAKE);
+        assert_eq!(handshake, json!({ "v": 1, "client_id": "1234" }));
+        let (opcode, payload) = frames.recv().await.unwrap();
+        assert_eq!(opcode, OP_FRAME);
+        assert_eq!(payload["cmd"], "SET_ACTIVITY");
+        assert_eq!(payload["nonce"], "1");
+        assert_eq!(payload["args"]["pid"], process::id());
+        assert_eq!(payload["args"]["activity"]["details"], "Title");
+
+        // Errors keep the activity, pausing clears it
+        discord.write(&update(State::Error, None)).await.unwrap();
+        discord
+            .write(&update(State::Paused, Some(track(false))))
+            .await
+            .unwrap();
+        let (_, payload) = frames.recv().await.unwrap();
+        assert_eq!(payload["nonce"], "2");
+        assert!(payload["args"]["activity"].is_null());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use async_trait::async_trait;
+use image::ImageFormat;
+use std::{
+    env, fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    artwork,
+    providers::{Song, SongKind, State, Update},
+    sinks::{Error, Sink},
+    template::{self, DEFAULT_BAR_SONG, Templates},
+    utils::check_env_existence,
+};
+
+const DIR_ENV: &str = "FILES_DIR";
+const FORMAT_ENV: &str = "FILES_FORMAT";
+const IDLE_ENV: &str = "FILES_IDLE";
+const LIST_ENV: &str = "FILES_LIST";
+
+const TITLE_FILE: &str = "title.txt";
+const ARTIST_FILE: &str = "artist.txt";
+const ALBUM_FILE: &str = "album.txt";
+const NOW_PLAYING_FILE: &str = "nowplaying.txt";
+const COVER_FILE: &str = "cover.jpg";
+
+// Names accepted in `FILES_LIST`
+const FILES: [(&str, &str); 5] = [
+    ("title", TITLE_FILE),
+    ("artist", ARTIST_FILE),
+    ("album", ALBUM_FILE),
+    ("nowplaying", NOW_PLAYING_FILE),
+    ("cover", COVER_FILE),
+];
+
+pub fn enabled() -> bool {
+    check_env_existence(DIR_ENV, false)
+}
+
+/// Text files for OBS "read from file" sources, and the cover for image sources.
+pub struct Files {
+    dir: PathBuf,
+    /// File names to keep up to date
+    files: Vec<&'static str>,
+    templates: Templates,
+    /// `None` until the first cover is written, to replace leftovers of a previous run
+    previous_artwork: Option<Option<String>>,
+}
+
+// Readers either see the old file or the new one, never a truncated one
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+// Comma separated names from `FILES`, every file when empty
+fn parse_files(list: &str) -> Result<Vec<&'static str>, String> {
+    if list.trim().is_empty() {
+        return Ok(FILES.iter().map(|(_, file)| *file).collect());
+    }
+    list.split(',')
+        .map(|name| {
+            let name = name.trim().to_lowercase();
+            FILES
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, file)| *file)
+                .ok_or(format!("Unknown file `{}` in {}", name, LIST_ENV))
+        })
+        .collect()
+}
+
+// OBS image sources pick the decoder from the extension
+fn to_jpeg(content: &[u8]) -> Result<Vec<u8>, image::ImageError> {
+    let mut jpeg = Vec::new();
+    image::load_from_memory(content)?
+        .to_rgb8()
+        .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+    Ok(jpeg)
+}
+
+impl Files {
+    pub fn from_env() -> Self {
+        let files = match parse_files(&env::var(LIST_ENV).unwrap_or_default()) {
+            Ok(files) => files,
+            Err(err) => {
+                log::error!("{}", err);
+                process::exit(1);
+            }
+        };
+
+        Files {
+            dir: PathBuf::from(env::var(DIR_ENV).unwrap()),
+            files,
+            templates: Templates {
+                playing: template::from_env(FORMAT_ENV, DEFAULT_BAR_SONG),
+                paused: template::from_env(FORMAT_ENV, DEFAULT_BAR_SONG),
+                idle: template::from_env(IDLE_ENV, ""),
+            },
+            previous_artwork: None,
+        }
+    }
+
+    fn write_text(&self, file_name: &str, text: &str) -> io::Result<()> {
+        if !self.files.contains(&file_name) {
+            return Ok(());
+        }
+        write_atomic(&self.dir.join(file_name), text.as_bytes())
+    }
+
+    async fn write_cover(&mut self, song: Option<&Song>) -> Result<(), Error> {
+        if !self.files.contains(&COVER_FILE) {
+            return Ok(());
+        }
+        let url = song.and_then(|song| song.artwork_url.clone());
+        if self.previous_artwork.as_ref() == Some(&url) {
+            return Ok(());
+        }
+
+        let path = self.dir.join(COVER_FILE);
+        match &url {
+            Some(url) => write_atomic(&path, &to_jpeg(&artwork::fetch(url).await?)?)?,
+            None => remove_if_exists(&path)?,
+        }
+        self.previous_artwork = Some(url);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for Files {
+    fn name(&self) -> &'static str {
+        "Files"
+    }
+
+    async fn write(&mut self, update: &Update) -> Result<(), Error> {
+        // Keep showing the last song until the provider comes back
+        if update.state == State::Error {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+
+        let song = update.song.as_ref();
+        let artist = match song {
+            Some(song) if song.kind == SongKind::Episode => song.show.clone().unwrap_or_default(),
+            Some(song) if song.kind == SongKind::Track => song.artist(),
+            _ => String::new(),
+        };
+        self.write_text(TITLE_FILE, song.map_or("", |song| &song.title))?;
+        self.write_text(ARTIST_FILE, &artist)?;
+        self.write_text(ALBUM_FILE, song.map_or("", |song| &song.album))?;
+        self.write_text(
+            NOW_PLAYING_FILE,
+            &self.templates.render(song, update.platform),
+        )?;
+        self.write_cover(song).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        providers::{self, Event},
+        template::Template,
+    };
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("imaginal-files-{}-{}", process::id(), name));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn read(&self, file_name: &str) -> String {
+            fs::read_to_string(self.0.join(file_name)).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn files(dir: &TempDir, list: &str) -> Files {
+        let template = || Template::parse("{artist} - {title}").unwrap();
+        Files {
+            dir: dir.0.clone(),
+            files: parse_files(list).unwrap(),
+            templates: Templates {
+                playing: template(),
+                paused: template(),
+                idle: Template::parse("Nothing playing").unwrap(),
+            },
+            previous_artwork: None,
+        }
+    }
+
+    fn update(state: State, song: Option<Song>) -> Update {
+        Update {
+            platform: "Test",
+            timestamp: 0,
+            state,
+            event: Some(Event::TrackChange),
+            song,
+            artist: None,
+            error: None,
+        }
+    }
+
+    fn track(artwork_url: Option<String>) -> Song {
+        Song {
+            kind: SongKind::Track,
+            playing: true,
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string()],
+            album: "Album".to_string(),
+            artwork_url,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let dir = TempDir::new("atomic");
+        let path = dir.0.join("title.txt");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(dir.read("title.txt"), "second");
+        let names: Vec<_> = fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["title.txt"]);
+    }
+
+    #[test]
+    fn parses_the_file_list() {
+        assert_eq!(parse_files("").unwrap().len(), FILES.len());
+        assert_eq!(
+            parse_files(" NowPlaying, cover").unwrap(),
+            vec![NOW_PLAYING_FILE, COVER_FILE]
+        );
+        assert_eq!(
+            parse_files("title,lyrics").unwrap_err(),
+            "Unknown file `lyrics` in FILES_LIST"
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_the_song_and_clears_it_when_idle() {
+        let dir = TempDir::new("idle");
+        let cover = dir.0.join("source.png");
+        image::RgbImage::new(4, 4).save(&cover).unwrap();
+        let mut files = files(&dir, "");
+
+        let song = track(Some(format!("file://{}", cover.display())));
+        files
+            .write(&update(State::Playing, Some(song)))
+            .await
+            .unwrap();
+        assert_eq!(dir.read(TITLE_FILE), "Title");
+        assert_eq!(dir.read(ARTIST_FILE), "Artist");
+        assert_eq!(dir.read(ALBUM_FILE), "Album");
+        assert_eq!(dir.read(NOW_PLAYING_FILE), "Artist - Title");
+        let jpeg = fs::read(dir.0.join(COVER_FILE)).unwrap();
+        assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+
+        files.write(&update(State::Idle, None)).await.unwrap();
+        assert_eq!(dir.read(TITLE_FILE), "");
+        assert_eq!(dir.read(ARTIST_FILE), "");
+        assert_eq!(dir.read(NOW_PLAYING_FILE), "Nothing playing");
+        assert!(!dir.0.join(COVER_FILE).exists());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_song_on_errors() {
+        let dir = TempDir::new("errors");
+        let mut files = files(&dir, "");
+        files
+            .write(&update(State::Playing, Some(track(None))))
+            .await
+            .unwrap();
+
+        let mut failed = update(State::Error, None);
+        failed.error = Some(providers::Error::from(io::Error::other("broken")));
+        files.write(&failed).await.unwrap();
+        assert_eq!(dir.read(TITLE_FILE), "Title");
+        assert_eq!(dir.read(NOW_PLAYING_FILE), "Artist - Title");
+    }
+
+    #[tokio::test]
+    async fn only_writes_listed_files() {
+        let dir = TempDir::new("list");
+        // Left over from a previous run
+        fs::write(dir.0.join(COVER_FILE), b"old").unwrap();
+        let mut files = files(&dir, "nowplaying");
+
+        files
+            .write(&update(State::Playing, Some(track(None))))
+            .await
+            .unwrap();
+        assert_eq!(dir.read(NOW_PLAYING_FILE), "Artist - Title");
+        assert!(!dir.0.join(TITLE_FILE).exists());
+        assert!(!dir.0.join(ARTIST_FILE).exists());
+        assert_eq!(fs::read(dir.0.join(COVER_FILE)).unwrap(), b"old");
+    }
+}
@@ -0,0 +1,313 @@
+use async_trait::async_trait;
+use std::{collections::HashMap, env, process::Stdio, sync::Arc, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command, sync::Semaphore};
+
+use crate::{
+    providers::{Event, SongKind, Update},
+    sinks::{Error, Sink},
+};
+
+const HOOK_ENV_PREFIX: &str = "HOOK_ON_";
+const TIMEOUT_ENV: &str = "HOOK_TIMEOUT";
+const CONCURRENCY_ENV: &str = "HOOK_CONCURRENCY";
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_CONCURRENCY: usize = 4;
+
+const EVENTS: [Event; 5] = [
+    Event::TrackChange,
+    Event::Pause,
+    Event::Resume,
+    Event::Idle,
+    Event::Error,
+];
+
+fn hook_env(event: Event) -> String {
+    format!("{}{}", HOOK_ENV_PREFIX, event.name().to_uppercase())
+}
+
+pub fn enabled() -> bool {
+    EVENTS
+        .iter()
+        .any(|event| env::var(hook_env(*event)).is_ok())
+}
+
+/// User commands run through `sh -c` on events, without blocking the updates.
+pub struct Hooks {
+    commands: HashMap<&'static str, String>,
+    timeout: Duration,
+    /// Hooks past the limit wait for a running one to finish
+    permits: Arc<Semaphore>,
+}
+
+fn parse_env<T: std::str::FromStr>(var: &str, default: T) -> T {
+    match env::var(var).map(|value| value.parse()) {
+        Ok(Ok(value)) => value,
+        Ok(Err(_)) => {
+            log::warn!("Invalid {}, using the default", var);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+fn hook_vars(update: &Update) -> Vec<(&'static str, String)> {
+    let mut vars = vec![
+        (
+            "IMAGINAL_EVENT",
+            update
+                .event
+                .map(|e| e.name())
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        ("IMAGINAL_PLATFORM", update.platform.to_string()),
+        ("IMAGINAL_TIMESTAMP", update.timestamp.to_string()),
+    ];
+    if let Some(err) = &update.error {
+        vars.push(("IMAGINAL_ERROR", err.to_string()));
+    }
+
+    if let Some(song) = &update.song {
+        let kind = match song.kind {
+            SongKind::Track => "track",
+            SongKind::Episode => "episode",
+            SongKind::Ad => "ad",
+            SongKind::Unknown => "unknown",
+        };
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let millis = |value: Option<u64>| value.map(|ms| ms.to_string()).unwrap_or_default();
+
+        vars.extend([
+            ("IMAGINAL_KIND", kind.to_string()),
+            ("IMAGINAL_PLAYING", song.playing.to_string()),
+            ("IMAGINAL_TITLE", song.title.clone()),
+            ("IMAGINAL_ARTIST", song.artist()),
+            ("IMAGINAL_ALBUM", song.album.clone()),
+            ("IMAGINAL_DURATION_MS", millis(song.duration_ms)),
+            ("IMAGINAL_PROGRESS_MS", millis(song.progress_ms)),
+            ("IMAGINAL_URL", optional(&song.url)),
+            ("IMAGINAL_ARTWORK_URL", optional(&song.artwork_url)),
+            ("IMAGINAL_ID", optional(&song.id)),
+            ("IMAGINAL_SHOW", optional(&song.show)),
+            ("IMAGINAL_PUBLISHER", optional(&song.publisher)),
+        ]);
+    }
+    vars
+}
+
+async fn run(command: &str, vars: Vec<(&'static str, String)>, stdin: String, timeout: Duration) {
+    // Hooks printing to our stdout would break bar outputs
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(vars)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            log::error!("Couldn't run hook `{}`: {}", command, err);
+            return;
+        }
+    };
+
+    let stdin_pipe = child.stdin.take();
+    let output = async {
+        // Hooks ignoring stdin close it early, that's fine
+        if let Some(mut pipe) = stdin_pipe {
+            let _ = pipe.write_all(stdin.as_bytes()).await;
+        }
+        child.wait_with_output().await
+    };
+
+    match tokio::time::timeout(timeout, output).await {
+        Ok(Ok(output)) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if !stdout.trim().is_empty() {
+                log::debug!("Hook `{}` output: {}", command, stdout.trim());
+            }
+        }
+        Ok(Ok(output)) => log::warn!(
+            "Hook `{}` failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(Err(err)) => log::error!("Couldn't wait for hook `{}`: {}", command, err),
+        Err(_) => log::warn!("Hook `{}` killed after {:?}", command, timeout),
+    }
+}
+
+impl Hooks {
+    pub fn from_env() -> Self {
+        let commands = EVENTS
+            .iter()
+            .filter_map(|event| {
+                let command = env::var(hook_env(*event)).ok()?;
+                Some((event.name(), command))
+            })
+            .collect();
+        let concurrency = parse_env(CONCURRENCY_ENV, DEFAULT_CONCURRENCY).max(1);
+
+        Hooks {
+            commands,
+            timeout: Duration::from_secs(parse_env(TIMEOUT_ENV, DEFAULT_TIMEOUT_SECS)),
+            permits: Arc::new(Semaphore::new(concurrency)),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for Hooks {
+    fn name(&self) -> &'static str {
+        "Hooks"
+    }
+
+    async fn write(&mut self, update: &Update) -> Result<(), Error> {
+        let command = match update
+            .event
+            .and_then(|event| self.commands.get(event.name()))
+        {
+            Some(command) => command.clone(),
+            None => return Ok(()),
+        };
+
+        let vars = hook_vars(update);
+        let stdin = serde_json::to_string(update)?;
+        let timeout = self.timeout;
+        let permits = self.permits.clone();
+        tokio::spawn(async move {
+            // Never closed, acquiring can't fail
+            let _permit = permits.acquire_owned().await;
+            run(&command, vars, stdin, timeout).await;
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{self, Song, State};
+    use std::{fs, io, path::PathBuf, process};
+
+    fn update(event: Event, timestamp: u64, song: Option<Song>) -> Update {
+        Update {
+            platform: "Test",
+            timestamp,
+            state: State::Playing,
+            event: Some(event),
+            song,
+            artist: None,
+            error: None,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("imaginal-hooks-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn exposes_the_song_as_variables() {
+        let song = Song {
+            kind: SongKind::Episode,
+            playing: true,
+            title: "Title".to_string(),
+            duration_ms: Some(180_000),
+            show: Some("Show".to_string()),
+            ..Default::default()
+        };
+        let vars: HashMap<_, _> = hook_vars(&update(Event::TrackChange, 42, Some(song)))
+            .into_iter()
+            .collect();
+
+        assert_eq!(vars["IMAGINAL_EVENT"], "track_change");
+        assert_eq!(vars["IMAGINAL_PLATFORM"], "Test");
+        assert_eq!(vars["IMAGINAL_TIMESTAMP"], "42");
+        assert_eq!(vars["IMAGINAL_KIND"], "episode");
+        assert_eq!(vars["IMAGINAL_PLAYING"], "true");
+        assert_eq!(vars["IMAGINAL_TITLE"], "Title");
+        assert_eq!(vars["IMAGINAL_DURATION_MS"], "180000");
+        assert_eq!(vars["IMAGINAL_PROGRESS_MS"], "");
+        assert_eq!(vars["IMAGINAL_SHOW"], "Show");
+        assert_eq!(vars["IMAGINAL_URL"], "");
+        assert!(!vars.contains_key("IMAGINAL_ERROR"));
+    }
+
+    #[test]
+    fn exposes_errors_without_a_song() {
+        let mut failed = update(Event::Error, 42, None);
+        failed.error = Some(providers::Error::from(io::Error::other("broken")));
+        let vars: HashMap<_, _> = hook_vars(&failed).into_iter().collect();
+
+        assert_eq!(vars["IMAGINAL_EVENT"], "error");
+        assert_eq!(vars["IMAGINAL_ERROR"], "Unknown: broken");
+        assert!(!vars.contains_key("IMAGINAL_TITLE"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn kills_hooks_past_the_timeout() {
+        let dir = temp_dir("timeout");
+        let marker = dir.join("marker");
+        let command = format!("sleep 1; touch '{}'", marker.display());
+
+        let start = tokio::time::Instant::now();
+        run(&command, vec![], String::new(), Duration::from_secs(5)).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        // Long enough for the hook to finish had it been left running
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn runs_hooks_up_to_the_concurrency_limit() {
+        let dir = temp_dir("concurrency");
+        let log = dir.join("log");
+        let command = format!(
+            "echo \"start $IMAGINAL_TIMESTAMP\" >> '{log}'; sleep 0.2; echo \"end $(cat)\" >> '{log}'",
+            log = log.display()
+        );
+        let mut hooks = Hooks {
+            commands: HashMap::from([(Event::TrackChange.name(), command)]),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            permits: Arc::new(Semaphore::new(1)),
+        };
+
+        hooks
+            .write(&update(Event::TrackChange, 1, None))
+            .await
+            .unwrap();
+        hooks
+            .write(&update(Event::TrackChange, 2, None))
+            .await
+            .unwrap();
+        // No command for pauses
+        hooks.write(&update(Event::Pause, 3, None)).await.unwrap();
+
+        let lines = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let content = fs::read_to_string(&log).unwrap_or_default();
+                if content.lines().count() == 4 {
+                    break content;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+
+        assert_eq!(lines[0], "start 1");
+        assert!(lines[1].starts_with("end {") && lines[1].contains("\"timestamp\":1"));
+        assert_eq!(lines[2], "start 2");
+        assert!(lines[3].contains("\"timestamp\":2"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,495 @@
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::{
+    env, process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    providers::{Song, State, Update},
+    sinks::{Error, Sink},
+    utils::check_env_existence,
+};
+
+const HOST_ENV: &str = "MQTT_HOST";
+const PORT_ENV: &str = "MQTT_PORT";
+const USERNAME_ENV: &str = "MQTT_USERNAME";
+const PASSWORD_ENV: &str = "MQTT_PASSWORD";
+const TOPIC_ENV: &str = "MQTT_TOPIC";
+const DISCOVERY_ENV: &str = "MQTT_DISCOVERY_PREFIX";
+const DEFAULT_PORT: u16 = 1883;
+const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+// Room for the discovery configs and a couple of updates while disconnected
+const CHANNEL_CAPACITY: usize = 64;
+const RECONNECT_SECS: u64 = 5;
+
+pub fn enabled() -> bool {
+    check_env_existence(HOST_ENV, false)
+}
+
+type Messages = Vec<(String, String)>;
+
+/// Retained state on an MQTT broker, announced to Home Assistant.
+pub struct Mqtt {
+    client: AsyncClient,
+    topic: String,
+    /// Latest update messages, published again on reconnection
+    retained: Arc<Mutex<Messages>>,
+    connected: Arc<AtomicBool>,
+}
+
+// `imaginal/<user>` unless MQTT_TOPIC is set
+fn base_topic() -> String {
+    match env::var(TOPIC_ENV) {
+        Ok(topic) if !topic.trim().is_empty() => topic.trim().trim_end_matches('/').to_string(),
+        _ => {
+            let user = env::var("USER").unwrap_or("default".to_string());
+            format!("imaginal/{}", user)
+        }
+    }
+}
+
+// Home Assistant ids only allow letters, digits, `_` and `-`
+fn object_id(topic: &str) -> String {
+    topic
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::Playing => "playing",
+        State::Paused => "paused",
+        State::Idle => "idle",
+        State::Error => "error",
+    }
+}
+
+// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+fn discovery(prefix: &str, topic: &str) -> Messages {
+    let id = object_id(topic);
+    let device = json!({
+        "identifiers": [id],
+        "name": format!("imaginal {}", topic),
+        "manufacturer": "imaginal",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let availability = format!("{}/availability", topic);
+
+    let now_playing = json!({
+        "name": "Now playing",
+        "unique_id": format!("{}_now_playing", id),
+        "icon": "mdi:music",
+        "state_topic": format!("{}/state", topic),
+        "value_template": "{% if value_json.song %}{% if value_json.artist %}{{ value_json.artist }} - {% endif %}{{ value_json.song.title }}{% else %}{{ value_json.state }}{% endif %}",
+        "json_attributes_topic": format!("{}/state", topic),
+        "availability_topic": availability,
+        "device": device,
+    });
+    let playback = json!({
+        "name": "Playback",
+        "unique_id": format!("{}_playback", id),
+        "icon": "mdi:play-pause",
+        "state_topic": format!("{}/playback", topic),
+        "availability_topic": availability,
+        "device": device,
+    });
+
+    vec![
+        (
+            format!("{}/sensor/{}/now_playing/config", prefix, id),
+            now_playing.to_string(),
+        ),
+        (
+            format!("{}/sensor/{}/playback/config", prefix, id),
+            playback.to_string(),
+        ),
+    ]
+}
+
+fn publish_all(client: &AsyncClient, messages: &[(String, String)]) {
+    for (topic, payload) in messages {
+        if let Err(err) = client.try_publish(topic, QoS::AtLeastOnce, true, payload.as_bytes()) {
+            log::warn!("Couldn't publish to {}: {}", topic, err);
+        }
+    }
+}
+
+// The event loop does the actual networking and reconnects by itself
+async fn run(
+    mut eventloop: EventLoop,
+    client: AsyncClient,
+    announcements: Messages,
+    retained: Arc<Mutex<Messages>>,
+    connected: Arc<AtomicBool>,
+) {
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("Connected to the MQTT broker");
+                connected.store(true, Ordering::Relaxed);
+                // The broker may have restarted or missed updates in between
+                publish_all(&client, &announcements);
+                publish_all(&client, &retained.lock().unwrap());
+            }
+            Ok(_) => {}
+            Err(err) => {
+                if connected.swap(false, Ordering::Relaxed) {
+                    log::warn!("Lost the connection to the MQTT broker: {}", err);
+                } else {
+                    log::debug!("Couldn't connect to the MQTT broker: {}", err);
+                }
+                tokio::time::sleep(Duration::from_secs(RECONNECT_SECS)).await;
+            }
+        }
+    }
+}
+
+impl Mqtt {
+    pub fn from_env() -> Self {
+        let host = env::var(HOST_ENV).unwrap();
+        let port = match env::var(PORT_ENV).map(|port| port.parse()) {
+            Ok(Ok(port)) => port,
+            Ok(Err(_)) => {
+                log::error!("{} must be a port number", PORT_ENV);
+                process::exit(1);
+            }
+            Err(_) => DEFAULT_PORT,
+        };
+        let topic = base_topic();
+        let availability = format!("{}/availability", topic);
+
+        let client_id = format!("imaginal-{}-{}", object_id(&topic), process::id());
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_last_will(LastWill::new(
+            &availability,
+            OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Ok(username) = env::var(USERNAME_ENV) {
+            options.set_credentials(username, env::var(PASSWORD_ENV).unwrap_or_default());
+        }
+
+        let mut announcements = vec![(availability, ONLINE.to_string())];
+        // An empty prefix turns discovery off
+        let prefix = env::var(DISCOVERY_ENV).unwrap_or(DEFAULT_DISCOVERY_PREFIX.to_string());
+        if !prefix.is_empty() {
+            announcements.extend(discovery(&prefix, &topic));
+        }
+
+        Self::new(options, topic, announcements)
+    }
+
+    fn new(options: MqttOptions, topic: String, announcements: Messages) -> Self {
+        let (client, eventloop) = AsyncClient::new(options, CHANNEL_CAPACITY);
+        let retained = Arc::new(Mutex::new(Vec::new()));
+        let connected = Arc::new(AtomicBool::new(false));
+        tokio::spawn(run(
+            eventloop,
+            client.clone(),
+            announcements,
+            retained.clone(),
+            connected.clone(),
+        ));
+
+        Mqtt {
+            client,
+            topic,
+            retained,
+            connected,
+        }
+    }
+
+    fn messages(&self, update: &Update) -> Result<Messages, Error> {
+        let song = update.song.as_ref();
+        let field = |value: fn(&Song) -> Option<String>| song.and_then(value).unwrap_or_default();
+
+        let fields = [
+            ("state", serde_json::to_string(update)?),
+            ("playback", state_name(update.state).to_string()),
+            (
+                "event",
+                update
+                    .event
+                    .map(|event| event.name())
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            ("title", field(|song| Some(song.title.clone()))),
+            ("artist", update.artist.clone().unwrap_or_default()),
+            ("album", field(|song| Some(song.album.clone()))),
+            (
+                "duration_ms",
+                field(|song| song.duration_ms.map(|ms| ms.to_string())),
+            ),
+            (
+                "progress_ms",
+                field(|song| song.progress_ms.map(|ms| ms.to_string())),
+            ),
+            ("url", field(|song| song.url.clone())),
+            ("artwork_url", field(|song| song.artwork_url.clone())),
+        ];
+
+        Ok(fields
+            .into_iter()
+            .map(|(name, payload)| (format!("{}/{}", self.topic, name), payload))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Sink for Mqtt {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    async fn write(&mut self, update: &Update) -> Result<(), Error> {
+        let messages = self.messages(update)?;
+        *self.retained.lock().unwrap() = messages.clone();
+        // Never waits on the broker, a full channel only drops this update.
+        // While disconnected, the latest one is published on reconnection.
+        if self.connected.load(Ordering::Relaxed) {
+            publish_all(&self.client, &messages);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{self, Event, SongKind};
+    use std::{collections::HashMap, io};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    const TOPIC: &str = "imaginal/office";
+
+    fn update(state: State, song: Option<Song>) -> Update {
+        let artist = song.as_ref().map(|song| song.artist());
+        Update {
+            platform: "Test",
+            timestamp: 1_760_000_000,
+            state,
+            event: Some(Event::TrackChange),
+            song,
+            artist,
+            error: None,
+        }
+    }
+
+    fn track() -> Song {
+        Song {
+            kind: SongKind::Track,
+            playing: true,
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string(), "Guest".to_string()],
+            album: "Album".to_string(),
+            duration_ms: Some(180_000),
+            progress_ms: Some(42_000),
+            artwork_url: Some("https://example.com/cover.jpg".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn by_topic(messages: &Messages) -> HashMap<&str, &str> {
+        messages
+            .iter()
+            .map(|(topic, payload)| (topic.as_str(), payload.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn ids_only_keep_safe_characters() {
+        assert_eq!(object_id("imaginal/my user"), "imaginal_my_user");
+    }
+
+    #[test]
+    fn announces_both_sensors() {
+        let messages = discovery("homeassistant", TOPIC);
+        let topics: Vec<&str> = messages.iter().map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "homeassistant/sensor/imaginal_office/now_playing/config",
+                "homeassistant/sensor/imaginal_office/playback/config",
+            ]
+        );
+
+        let now_playing: serde_json::Value = serde_json::from_str(&messages[0].1).unwrap();
+        assert_eq!(now_playing["unique_id"], "imaginal_office_now_playing");
+        assert_eq!(now_playing["state_topic"], "imaginal/office/state");
+        assert_eq!(
+            now_playing["json_attributes_topic"],
+            "imaginal/office/state"
+        );
+        assert_eq!(
+            now_playing["availability_topic"],
+            "imaginal/office/availability"
+        );
+        assert_eq!(now_playing["device"]["identifiers"][0], "imaginal_office");
+
+        let playback: serde_json::Value = serde_json::from_str(&messages[1].1).unwrap();
+        assert_eq!(playback["unique_id"], "imaginal_office_playback");
+        assert_eq!(playback["state_topic"], "imaginal/office/playback");
+        assert_eq!(
+            playback["availability_topic"],
+            "imaginal/office/availability"
+        );
+    }
+
+    fn sink() -> Mqtt {
+        // Never polled, nothing gets sent
+        let (client, _) = AsyncClient::new(MqttOptions::new("test", "127.0.0.1", 1), 10);
+        Mqtt {
+            client,
+            topic: TOPIC.to_string(),
+            retained: Arc::default(),
+            connected: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn splits_updates_into_field_topics() {
+        let messages = sink()
+            .messages(&update(State::Playing, Some(track())))
+            .unwrap();
+        let payloads = by_topic(&messages);
+
+        assert_eq!(payloads.len(), 10);
+        assert_eq!(payloads["imaginal/office/playback"], "playing");
+        assert_eq!(payloads["imaginal/office/event"], "track_change");
+        assert_eq!(payloads["imaginal/office/title"], "Title");
+        assert_eq!(payloads["imaginal/office/artist"], "Artist, Guest");
+        assert_eq!(payloads["imaginal/office/album"], "Album");
+        assert_eq!(payloads["imaginal/office/duration_ms"], "180000");
+        assert_eq!(payloads["imaginal/office/progress_ms"], "42000");
+        assert_eq!(payloads["imaginal/office/url"], "");
+        assert_eq!(
+            payloads["imaginal/office/artwork_url"],
+            "https://example.com/cover.jpg"
+        );
+
+        let state: serde_json::Value =
+            serde_json::from_str(payloads["imaginal/office/state"]).unwrap();
+        assert_eq!(state["state"], "playing");
+        assert_eq!(state["artist"], "Artist, Guest");
+        assert_eq!(state["song"]["title"], "Title");
+    }
+
+    #[test]
+    fn clears_fields_when_idle_or_failing() {
+        let sink = sink();
+
+        let messages = sink.messages(&update(State::Idle, None)).unwrap();
+        let payloads = by_topic(&messages);
+        assert_eq!(payloads["imaginal/office/playback"], "idle");
+        assert_eq!(payloads["imaginal/office/title"], "");
+        assert_eq!(payloads["imaginal/office/artist"], "");
+        assert_eq!(payloads["imaginal/office/duration_ms"], "");
+
+        let mut failed = update(State::Error, None);
+        failed.event = Some(Event::Error);
+        failed.error = Some(providers::Error::from(io::Error::other("broken")));
+        let messages = sink.messages(&failed).unwrap();
+        let payloads = by_topic(&messages);
+        assert_eq!(payloads["imaginal/office/playback"], "error");
+        assert_eq!(payloads["imaginal/office/event"], "error");
+        assert_eq!(payloads["imaginal/office/title"], "");
+        let state: serde_json::Value =
+            serde_json::from_str(payloads["imaginal/office/state"]).unwrap();
+        assert_eq!(state["error"]["message"], "broken");
+        assert!(state["song"].is_null());
+    }
+
+    /// Bare MQTT 3.1.1 broker for a single client, forwarding its publishes.
+    async fn broker() -> (u16, mpsc::UnboundedReceiver<(String, String, bool)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            while let Ok(header) = socket.read_u8().await {
+                let mut length = 0;
+                for shift in (0..28).step_by(7) {
+                    let byte = socket.read_u8().await.unwrap();
+                    length |= ((byte & 0x7f) as usize) << shift;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                let mut packet = vec![0; length];
+                socket.read_exact(&mut packet).await.unwrap();
+
+                match header >> 4 {
+                    // CONNECT, accepted
+                    1 => socket.write_all(&[0x20, 2, 0, 0]).await.unwrap(),
+                    // PUBLISH, acknowledged when QoS 1
+                    3 => {
+                        let topic_end = 2 + u16::from_be_bytes([packet[0], packet[1]]) as usize;
+                        let topic = String::from_utf8(packet[2..topic_end].to_vec()).unwrap();
+                        let mut payload_start = topic_end;
+                        if header & 0b110 != 0 {
+                            let id = &packet[topic_end..topic_end + 2];
+                            socket.write_all(&[0x40, 2, id[0], id[1]]).await.unwrap();
+                            payload_start += 2;
+                        }
+                        let payload = String::from_utf8(packet[payload_start..].to_vec()).unwrap();
+                        let _ = sender.send((topic, payload, header & 1 == 1));
+                    }
+                    // PINGREQ
+                    12 => socket.write_all(&[0xd0, 0]).await.unwrap(),
+                    _ => {}
+                }
+            }
+        });
+
+        (port, receiver)
+    }
+
+    #[tokio::test]
+    async fn publishes_retained_messages_to_the_broker() {
+        let (port, mut published) = broker().await;
+        let announcements = vec![(format!("{}/availability", TOPIC), ONLINE.to_string())];
+        let mut mqtt = Mqtt::new(
+            MqttOptions::new("test", "127.0.0.1", port),
+            TOPIC.to_string(),
+            announcements,
+        );
+
+        // Sent before or after the connection, it ends up on the broker either way
+        mqtt.write(&update(State::Playing, Some(track())))
+            .await
+            .unwrap();
+
+        let mut received = HashMap::new();
+        while received.len() < 11 {
+            let (topic, payload, retain) =
+                tokio::time::timeout(Duration::from_secs(5), published.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert!(retain, "{} isn't retained", topic);
+            received.insert(topic, payload);
+        }
+
+        assert_eq!(received["imaginal/office/availability"], ONLINE);
+        assert_eq!(received["imaginal/office/playback"], "playing");
+        assert_eq!(received["imaginal/office/title"], "Title");
+        assert_eq!(received["imaginal/office/artist"], "Artist, Guest");
+    }
+}
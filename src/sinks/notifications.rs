@@ -0,0 +1,379 @@
+use async_trait::async_trait;
+use std::{collections::HashMap, env, fs};
+use zbus::{Connection, zvariant::Value};
+
+use crate::{
+    artwork, database,
+    output::escape_markup,
+    providers::{Event, Song, SongKind, Update},
+    sinks::{Error, Sink},
+    utils::check_env_existence,
+};
+
+const EVENTS_ENV: &str = "NOTIFY_EVENTS";
+const DEFAULT_EVENTS: &str = "track_change";
+
+// https://specifications.freedesktop.org/notification-spec/latest/protocol.html
+const DESTINATION: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+const APP_NAME: &str = "imaginal";
+const DEFAULT_TIMEOUT: i32 = -1;
+
+pub fn enabled() -> bool {
+    check_env_existence(EVENTS_ENV, false)
+}
+
+/// Desktop notifications, replacing each other instead of stacking up.
+pub struct Notifications {
+    events: Vec<String>,
+    connection: Option<Connection>,
+    /// Id of the notification on screen, replaced by the next one
+    replaces_id: u32,
+}
+
+// Covers need to be local files for the notification daemon
+async fn image_path(song: &Song) -> Option<String> {
+    let url = song.artwork_url.as_ref()?;
+    if url.starts_with("file://") {
+        return Some(url.clone());
+    }
+
+    if let Err(err) = artwork::fetch(url).await {
+        log::warn!("Couldn't fetch artwork {}: {}", url, err);
+        return None;
+    }
+    let path = fs::canonicalize(database::artwork::get_artwork_path(url)).ok()?;
+    Some(format!("file://{}", path.display()))
+}
+
+fn message(event: Event, update: &Update) -> (String, String) {
+    let song = match (&update.song, &update.error) {
+        (_, Some(err)) => return ("Error".to_string(), escape_markup(&err.to_string())),
+        (Some(song), None) => song,
+        (None, None) => return ("Nothing playing".to_string(), String::new()),
+    };
+
+    let title = match song.kind {
+        SongKind::Ad => "Advertisement".to_string(),
+        _ => song.title.clone(),
+    };
+    let summary = match event {
+        Event::Pause => format!("Paused: {}", title),
+        _ => title,
+    };
+    let lines = match song.kind {
+        SongKind::Episode => vec![
+            song.show.clone().unwrap_or_default(),
+            song.publisher.clone().unwrap_or_default(),
+        ],
+        SongKind::Track => vec![song.artist(), song.album.clone()],
+        _ => vec![],
+    };
+    let body = lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| escape_markup(line))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    (summary, body)
+}
+
+// Comma separated event names, `track_change` only when empty
+fn parse_events(events: &str) -> Vec<String> {
+    let events = match events.trim() {
+        "" => DEFAULT_EVENTS,
+        events => events,
+    };
+    events
+        .split(',')
+        .map(|event| event.trim().to_lowercase())
+        .collect()
+}
+
+impl Notifications {
+    pub fn from_env() -> Self {
+        Notifications {
+            events: parse_events(&env::var(EVENTS_ENV).unwrap_or_default()),
+            connection: None,
+            replaces_id: 0,
+        }
+    }
+
+    async fn notify(
+        &mut self,
+        summary: &str,
+        body: &str,
+        image_path: Option<String>,
+    ) -> Result<(), Error> {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(Connection::session().await?),
+        };
+
+        let mut hints: HashMap<&str, Value> = HashMap::new();
+        if let Some(image_path) = &image_path {
+            hints.insert("image-path", Value::from(image_path.as_str()));
+        }
+        let actions: Vec<&str> = Vec::new();
+
+        let reply = connection
+            .call_method(
+                Some(DESTINATION),
+                OBJECT_PATH,
+                Some(DESTINATION),
+                "Notify",
+                &(
+                    APP_NAME,
+                    self.replaces_id,
+                    image_path.as_deref().unwrap_or_default(),
+                    summary,
+                    body,
+                    actions,
+                    hints,
+                    DEFAULT_TIMEOUT,
+                ),
+            )
+            .await;
+
+        match reply {
+            Ok(reply) => {
+                self.replaces_id = reply.body().deserialize()?;
+                Ok(())
+            }
+            Err(err) => {
+                // The session bus may have restarted, connect again next time
+                self.connection = None;
+                Err(err.into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for Notifications {
+    fn name(&self) -> &'static str {
+        "Notifications"
+    }
+
+    async fn write(&mut self, update: &Update) -> Result<(), Error> {
+        let event = match update.event {
+            Some(event) if self.events.iter().any(|name| name == event.name()) => event,
+            _ => return Ok(()),
+        };
+
+        let (summary, body) = message(event, update);
+        let image_path = match &update.song {
+            Some(song) => image_path(song).await,
+            None => None,
+        };
+        self.notify(&summary, &body, image_path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use zbus::{Guid, connection, zvariant::OwnedValue};
+
+    fn track(playing: bool) -> Song {
+        Song {
+            kind: SongKind::Track,
+            playing,
+            title: "Rock & Roll".to_string(),
+            artists: vec!["<Artist>".to_string()],
+            album: "Album".to_string(),
+            artwork_url: Some("file:///cover.jpg".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn update(event: Event, song: Option<Song>) -> Update {
+        Update {
+            platform: "Test",
+            timestamp: 0,
+            state: crate::providers::State::Playing,
+            event: Some(event),
+            song,
+            artist: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn describes_tracks() {
+        let (summary, body) = message(
+            Event::TrackChange,
+            &update(Event::TrackChange, Some(track(true))),
+        );
+        assert_eq!(summary, "Rock & Roll");
+        assert_eq!(body, "&lt;Artist&gt;\nAlbum");
+
+        let (summary, _) = message(Event::Pause, &update(Event::Pause, Some(track(false))));
+        assert_eq!(summary, "Paused: Rock & Roll");
+    }
+
+    #[test]
+    fn describes_episodes_ads_and_errors() {
+        let episode = Song {
+            kind: SongKind::Episode,
+            title: "Episode".to_string(),
+            show: Some("Show".to_string()),
+            publisher: None,
+            ..track(true)
+        };
+        let (summary, body) = message(
+            Event::TrackChange,
+            &update(Event::TrackChange, Some(episode)),
+        );
+        assert_eq!(summary, "Episode");
+        assert_eq!(body, "Show");
+
+        let ad = Song {
+            kind: SongKind::Ad,
+            ..track(true)
+        };
+        let (summary, body) = message(Event::TrackChange, &update(Event::TrackChange, Some(ad)));
+        assert_eq!(summary, "Advertisement");
+        assert_eq!(body, "");
+
+        let (summary, body) = message(Event::Idle, &update(Event::Idle, None));
+        assert_eq!(summary, "Nothing playing");
+        assert_eq!(body, "");
+
+        let mut failed = update(Event::Error, None);
+        failed.error = Some(std::io::Error::other("<broken>").into());
+        let (summary, body) = message(Event::Error, &failed);
+        assert_eq!(summary, "Error");
+        assert_eq!(body, "Unknown: &lt;broken&gt;");
+    }
+
+    type Calls = Arc<Mutex<Vec<(u32, String, String, String)>>>;
+
+    /// Notification daemon stand-in, numbering notifications from 1.
+    struct Daemon {
+        calls: Calls,
+        last_id: u32,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl Daemon {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &mut self,
+            _app_name: String,
+            replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let image_path = hints
+                .get("image-path")
+                .and_then(|value| String::try_from(value.try_clone().unwrap()).ok())
+                .unwrap_or_default();
+            self.calls
+                .lock()
+                .unwrap()
+                .push((replaces_id, summary, body, image_path));
+            match replaces_id {
+                0 => {
+                    self.last_id += 1;
+                    self.last_id
+                }
+                id => id,
+            }
+        }
+    }
+
+    #[test]
+    fn parses_event_names() {
+        assert_eq!(
+            parse_events(" Track_Change, pause"),
+            vec!["track_change", "pause"]
+        );
+        assert_eq!(parse_events(" "), vec!["track_change"]);
+    }
+
+    /// Sink talking to a daemon over a private connection instead of the session bus.
+    /// The daemon runs as long as its connection is kept.
+    async fn notifications(events: &[&str]) -> (Notifications, Calls, Connection) {
+        let calls = Calls::default();
+        let daemon = Daemon {
+            calls: calls.clone(),
+            last_id: 0,
+        };
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let server = connection::Builder::unix_stream(server)
+            .server(Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at(OBJECT_PATH, daemon)
+            .unwrap()
+            .build();
+        let client = connection::Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::join!(server, client);
+
+        let notifications = Notifications {
+            events: events.iter().map(|event| event.to_string()).collect(),
+            connection: Some(client.unwrap()),
+            replaces_id: 0,
+        };
+        (notifications, calls, server.unwrap())
+    }
+
+    #[tokio::test]
+    async fn replaces_the_previous_notification() {
+        let (mut notifications, calls, _daemon) = notifications(&["track_change", "pause"]).await;
+
+        notifications
+            .write(&update(Event::TrackChange, Some(track(true))))
+            .await
+            .unwrap();
+        notifications
+            .write(&update(Event::Pause, Some(track(false))))
+            .await
+            .unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            vec![
+                (
+                    0,
+                    "Rock & Roll".to_string(),
+                    "&lt;Artist&gt;\nAlbum".to_string(),
+                    "file:///cover.jpg".to_string()
+                ),
+                (
+                    1,
+                    "Paused: Rock & Roll".to_string(),
+                    "&lt;Artist&gt;\nAlbum".to_string(),
+                    "file:///cover.jpg".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_notifies_chosen_events() {
+        let (mut notifications, calls, _daemon) = notifications(&["resume"]).await;
+
+        for event in [Event::TrackChange, Event::Pause, Event::Heartbeat] {
+            notifications
+                .write(&update(event, Some(track(true))))
+                .await
+                .unwrap();
+        }
+        assert!(calls.lock().unwrap().is_empty());
+
+        notifications
+            .write(&update(Event::Resume, Some(track(true))))
+            .await
+            .unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,471 @@
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::{HashMap, VecDeque},
+    env, time,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    database,
+    providers::{Event, Update},
+    sinks::{Error, Sink},
+    utils::check_env_existence,
+};
+
+const URLS_ENV: &str = "WEBHOOK_URLS";
+const SECRET_ENV: &str = "WEBHOOK_SECRET";
+
+const SIGNATURE_HEADER: &str = "X-Imaginal-Signature";
+const EVENT_HEADER: &str = "X-Imaginal-Event";
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+// Retries after 5s, 10s, 20s... up to an hour, about 9 hours in total
+const FIRST_RETRY_SECS: u64 = 5;
+const MAX_RETRY_SECS: u64 = 3600;
+const MAX_ATTEMPTS: u32 = 15;
+// Per URL, older deliveries are dropped past that
+const MAX_QUEUED: usize = 100;
+// Nothing to retry, only new deliveries can wake the worker up
+const IDLE_SECS: u64 = 86400;
+
+pub fn enabled() -> bool {
+    check_env_existence(URLS_ENV, false)
+}
+
+/// A payload waiting to be sent, kept in the `database` folder until it is.
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    event: String,
+    body: String,
+    attempts: u32,
+    /// Seconds since UNIX epoch
+    next_attempt: u64,
+}
+
+/// POSTs every update to `WEBHOOK_URLS`, retrying failed ones in the background.
+pub struct Webhooks {
+    urls: Vec<String>,
+    sender: mpsc::UnboundedSender<Delivery>,
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// Receivers compute the same HMAC over the raw body to check it comes from us
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", signature)
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    FIRST_RETRY_SECS
+        .saturating_mul(1 << (attempts.saturating_sub(1)).min(32))
+        .min(MAX_RETRY_SECS)
+}
+
+fn push(deliveries: &mut VecDeque<Delivery>, delivery: Delivery) {
+    // Only the latest heartbeat matters, unless the previous one is being retried
+    let heartbeat = Event::Heartbeat.name();
+    if delivery.event == heartbeat
+        && deliveries.len() > 1
+        && deliveries
+            .back()
+            .is_some_and(|last| last.event == heartbeat)
+    {
+        deliveries.pop_back();
+    }
+    deliveries.push_back(delivery);
+
+    if deliveries.len() > MAX_QUEUED
+        && let Some(dropped) = deliveries.pop_front()
+    {
+        log::warn!(
+            "Too many pending webhooks to {}, dropping the oldest one",
+            dropped.url
+        );
+        // The backoff carries over, a dead receiver stays a dead receiver
+        if let Some(front) = deliveries.front_mut() {
+            front.attempts = dropped.attempts;
+            front.next_attempt = front.next_attempt.max(dropped.next_attempt);
+        }
+    }
+}
+
+struct Poster {
+    client: reqwest::Client,
+    secret: Option<String>,
+}
+
+impl Poster {
+    async fn send(&self, delivery: &Delivery) -> Result<(), reqwest::Error> {
+        let mut request = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .body(delivery.body.clone());
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &delivery.body));
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Sends the due deliveries of a URL in order, stopping at the first failure
+    /// so receivers never get an older update after a newer one.
+    async fn drain(&self, deliveries: &mut VecDeque<Delivery>, now: u64) -> bool {
+        let mut changed = false;
+
+        while let Some(delivery) = deliveries.front_mut() {
+            if delivery.next_attempt > now {
+                break;
+            }
+            changed = true;
+
+            match self.send(delivery).await {
+                Ok(()) => {
+                    log::debug!("Sent {} webhook to {}", delivery.event, delivery.url);
+                    deliveries.pop_front();
+                }
+                Err(err) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+                    log::error!(
+                        "Giving up on {} webhook to {} after {} attempts: {}",
+                        delivery.event,
+                        delivery.url,
+                        MAX_ATTEMPTS,
+                        err
+                    );
+                    deliveries.pop_front();
+                }
+                Err(err) => {
+                    delivery.attempts += 1;
+                    let delay = retry_delay(delivery.attempts);
+                    delivery.next_attempt = now + delay;
+                    log::warn!(
+                        "Couldn't send {} webhook to {}, retrying in {}s: {}",
+                        delivery.event,
+                        delivery.url,
+                        delay,
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+        changed
+    }
+}
+
+struct Worker {
+    poster: Poster,
+    /// Pending deliveries by URL, oldest first
+    queues: HashMap<String, VecDeque<Delivery>>,
+}
+
+impl Worker {
+    fn new(poster: Poster, deliveries: Vec<Delivery>) -> Self {
+        let mut queues: HashMap<String, VecDeque<Delivery>> = HashMap::new();
+        for delivery in deliveries {
+            queues
+                .entry(delivery.url.clone())
+                .or_default()
+                .push_back(delivery);
+        }
+        Worker { poster, queues }
+    }
+
+    // URLs don't wait for each other
+    async fn deliver_due(&mut self) -> bool {
+        let now = now();
+        let drains = self
+            .queues
+            .values_mut()
+            .map(|deliveries| self.poster.drain(deliveries, now));
+        let changed = join_all(drains).await.into_iter().any(|changed| changed);
+
+        self.queues.retain(|_, deliveries| !deliveries.is_empty());
+        changed
+    }
+
+    fn next_attempt(&self) -> Option<u64> {
+        self.queues
+            .values()
+            .filter_map(|deliveries| deliveries.front())
+            .map(|delivery| delivery.next_attempt)
+            .min()
+    }
+
+    fn save(&self) {
+        let deliveries: Vec<&Delivery> = self.queues.values().flatten().collect();
+        database::webhooks::set_queue(&deliveries);
+    }
+
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<Delivery>) {
+        let mut received = false;
+        loop {
+            if self.deliver_due().await || received {
+                self.save();
+            }
+
+            let wait = match self.next_attempt() {
+                Some(next_attempt) => next_attempt.saturating_sub(now()),
+                None => IDLE_SECS,
+            };
+            received = false;
+            tokio::select! {
+                delivery = receiver.recv() => match delivery {
+                    Some(delivery) => {
+                        let deliveries = self.queues.entry(delivery.url.clone()).or_default();
+                        push(deliveries, delivery);
+                        received = true;
+                    }
+                    None => return,
+                },
+                _ = tokio::time::sleep(time::Duration::from_secs(wait)) => {}
+            }
+        }
+    }
+}
+
+impl Webhooks {
+    pub fn from_env() -> Self {
+        let urls: Vec<String> = env::var(URLS_ENV)
+            .unwrap()
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+
+        // Deliveries to URLs removed since the last run are dropped
+        let mut queue: Vec<Delivery> = database::webhooks::get_queue();
+        queue.retain(|delivery| urls.contains(&delivery.url));
+        if !queue.is_empty() {
+            log::info!("Retrying {} webhooks from the last run", queue.len());
+        }
+
+        let poster = Poster {
+            client: reqwest::Client::builder()
+                .timeout(time::Duration::from_secs(REQUEST_TIMEOUT_SECS))
+                .build()
+                .unwrap(),
+            secret: env::var(SECRET_ENV)
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+        };
+        let worker = Worker::new(poster, queue);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(worker.run(receiver));
+
+        Webhooks { urls, sender }
+    }
+}
+
+#[async_trait]
+impl Sink for Webhooks {
+    fn name(&self) -> &'static str {
+        "Webhooks"
+    }
+
+    async fn write(&mut self, update: &Update) -> Result<(), Error> {
+        let event = match update.event {
+            Some(event) => event.name().to_string(),
+            None => return Ok(()),
+        };
+        let body = serde_json::to_string(update)?;
+
+        for url in &self.urls {
+            self.sender.send(Delivery {
+                url: url.clone(),
+                event: event.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: now(),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+    use std::sync::{Arc, Mutex};
+
+    fn delivery(url: &str, event: &str, body: &str) -> Delivery {
+        Delivery {
+            url: url.to_string(),
+            event: event.to_string(),
+            body: body.to_string(),
+            attempts: 0,
+            next_attempt: 0,
+        }
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        assert_eq!(
+            sign("secret", r#"{"state":"idle"}"#),
+            "sha256=ffdeaad465330dd85dd06471fac6075dbe6e52a71e85f69257d72935ccd81dfb"
+        );
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_an_hour() {
+        let delays: Vec<u64> = (1..=5).map(retry_delay).collect();
+        assert_eq!(delays, vec![5, 10, 20, 40, 80]);
+        assert_eq!(retry_delay(11), MAX_RETRY_SECS);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_SECS);
+    }
+
+    #[test]
+    fn coalesces_queued_heartbeats() {
+        let mut deliveries = VecDeque::new();
+        push(&mut deliveries, delivery("u", "heartbeat", "1"));
+        push(&mut deliveries, delivery("u", "heartbeat", "2"));
+        push(&mut deliveries, delivery("u", "heartbeat", "3"));
+        push(&mut deliveries, delivery("u", "pause", "4"));
+        push(&mut deliveries, delivery("u", "heartbeat", "5"));
+
+        let bodies: Vec<&str> = deliveries.iter().map(|d| d.body.as_str()).collect();
+        assert_eq!(bodies, vec!["1", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn caps_the_queue_keeping_the_backoff() {
+        let mut deliveries = VecDeque::new();
+        let mut first = delivery("u", "track_change", "0");
+        first.attempts = 4;
+        first.next_attempt = 1000;
+        push(&mut deliveries, first);
+        for i in 1..=MAX_QUEUED {
+            push(
+                &mut deliveries,
+                delivery("u", "track_change", &i.to_string()),
+            );
+        }
+
+        assert_eq!(deliveries.len(), MAX_QUEUED);
+        let front = deliveries.front().unwrap();
+        assert_eq!(front.body, "1");
+        assert_eq!((front.attempts, front.next_attempt), (4, 1000));
+    }
+
+    type Received = Arc<Mutex<Vec<(String, String, String)>>>;
+
+    /// Local receiver failing its first `failures` requests.
+    fn receiver(failures: usize) -> (String, Received) {
+        let received: Received = Arc::default();
+        let data = web::Data::new((received.clone(), Mutex::new(failures)));
+        let server = HttpServer::new(move || {
+            App::new().app_data(data.clone()).default_service(web::to(
+                |request: HttpRequest,
+                 body: String,
+                 data: web::Data<(Received, Mutex<usize>)>| async move {
+                    let mut failures = data.1.lock().unwrap();
+                    if *failures > 0 {
+                        *failures -= 1;
+                        return HttpResponse::InternalServerError().finish();
+                    }
+                    let header = |name: &str| {
+                        request
+                            .headers()
+                            .get(name)
+                            .map(|value| value.to_str().unwrap().to_string())
+                            .unwrap_or_default()
+                    };
+                    data.0.lock().unwrap().push((
+                        header(EVENT_HEADER),
+                        header(SIGNATURE_HEADER),
+                        body,
+                    ));
+                    HttpResponse::Ok().finish()
+                },
+            ))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/hook", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, received)
+    }
+
+    fn worker(deliveries: Vec<Delivery>) -> Worker {
+        let poster = Poster {
+            client: reqwest::Client::new(),
+            secret: Some("secret".to_string()),
+        };
+        Worker::new(poster, deliveries)
+    }
+
+    #[actix_web::test]
+    async fn delivers_in_order_after_failures() {
+        let (url, received) = receiver(1);
+        let mut worker = worker(vec![
+            delivery(&url, "track_change", "first"),
+            delivery(&url, "pause", "second"),
+        ]);
+
+        assert!(worker.deliver_due().await);
+        assert!(received.lock().unwrap().is_empty());
+        let front = worker.queues[&url].front().unwrap();
+        assert_eq!((front.body.as_str(), front.attempts), ("first", 1));
+        assert_eq!(worker.next_attempt(), Some(front.next_attempt));
+
+        // Nothing due until the backoff is over
+        assert!(!worker.deliver_due().await);
+        worker.queues.get_mut(&url).unwrap()[0].next_attempt = 0;
+        assert!(worker.deliver_due().await);
+        assert!(worker.queues.is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(
+            *received,
+            vec![
+                (
+                    "track_change".to_string(),
+                    sign("secret", "first"),
+                    "first".to_string()
+                ),
+                (
+                    "pause".to_string(),
+                    sign("secret", "second"),
+                    "second".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn failing_url_doesnt_hold_the_others() {
+        let (dead, _) = receiver(usize::MAX);
+        let (alive, received) = receiver(0);
+        let mut worker = worker(vec![
+            delivery(&dead, "track_change", "lost"),
+            delivery(&alive, "track_change", "sent"),
+        ]);
+
+        worker.deliver_due().await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(worker.queues.len(), 1);
+        assert_eq!(worker.queues[&dead].len(), 1);
+    }
+}
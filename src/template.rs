@@ -0,0 +1,369 @@
+use std::{env, process};
+
+use crate::providers::{Song, SongKind};
+
+const PLAYING_ENV: &str = "FORMAT_PLAYING";
+const PAUSED_ENV: &str = "FORMAT_PAUSED";
+const IDLE_ENV: &str = "FORMAT_IDLE";
+const TOOLTIP_ENV: &str = "FORMAT_TOOLTIP";
+
+// Same output as before templates existed
+const DEFAULT_SONG: &str = "{?track}{title} - {artist}\\nAlbum: {album}{/track}\
+    {?episode}{title} - {show}\\nPublisher: {publisher}{/episode}\
+    {?ad}Advertisement{/ad}\
+    {?unknown}Unknown content playing{/unknown}";
+const DEFAULT_IDLE: &str = "No song detected";
+
+// Status bars only have room for a single line
+pub const DEFAULT_BAR_SONG: &str = "{?track}{artist} - {title}{/track}\
+    {?episode}{show} - {title}{/episode}\
+    {?ad}Advertisement{/ad}";
+const DEFAULT_BAR_IDLE: &str = "";
+const DEFAULT_TOOLTIP: &str = "{title}{?artist}\\n{artist}{/artist}{?album}\\n{album}{/album}\
+    {?show}\\n{show}{/show}{?progress}\\n{progress}{?duration} / {duration}{/duration}{/progress}";
+
+const FIELDS: [&str; 16] = [
+    "title",
+    "artist",
+    "album",
+    "progress",
+    "duration",
+    "url",
+    "artwork",
+    "id",
+    "show",
+    "publisher",
+    "platform",
+    // Only non-empty for the matching kind, meant for conditionals
+    "track",
+    "episode",
+    "ad",
+    "unknown",
+    "playing",
+];
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Field(String),
+    /// `{?field}...{/field}`, or `{!field}...{/field}` when `negate`
+    Condition {
+        field: String,
+        negate: bool,
+        children: Vec<Node>,
+    },
+}
+
+/// A parsed now-playing line such as `{artist} - {title}{?album} [{album}]{/album}`.
+///
+/// `{field}` is replaced by the field value, `{?field}...{/field}` is only
+/// rendered when the field isn't empty and `{!field}...{/field}` only when it is.
+/// `{{`, `}}` and `\n` are escapes for `{`, `}` and a new line.
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let template = template.replace("\\n", "\n");
+        let mut chars = template.chars().peekable();
+        let nodes = parse_nodes(&mut chars, None)?;
+        Ok(Template { nodes })
+    }
+
+    pub fn render(&self, song: Option<&Song>, platform: &str) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, song, platform, &mut output);
+        output
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn parse_nodes(chars: &mut Chars, closing: Option<&str>) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(format!("Unclosed `{{{}`", tag)),
+                    }
+                }
+
+                if !text.is_empty() {
+                    nodes.push(Node::Text(std::mem::take(&mut text)));
+                }
+
+                if let Some(field) = tag.strip_prefix('/') {
+                    return match closing {
+                        Some(closing) if closing == field => Ok(nodes),
+                        _ => Err(format!("Unexpected `{{/{}}}`", field)),
+                    };
+                }
+
+                let (field, negate) = match tag.chars().next() {
+                    Some('?') => (&tag[1..], Some(false)),
+                    Some('!') => (&tag[1..], Some(true)),
+                    _ => (tag.as_str(), None),
+                };
+                if !FIELDS.contains(&field) {
+                    return Err(format!("Unknown field `{}`", field));
+                }
+
+                match negate {
+                    Some(negate) => nodes.push(Node::Condition {
+                        field: field.to_string(),
+                        negate,
+                        children: parse_nodes(chars, Some(field))?,
+                    }),
+                    None => nodes.push(Node::Field(field.to_string())),
+                }
+            }
+            '}' => return Err("Unexpected `}`, use `}}` to escape it".to_string()),
+            c => text.push(c),
+        }
+    }
+
+    if let Some(closing) = closing {
+        return Err(format!("Missing `{{/{}}}`", closing));
+    }
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    Ok(nodes)
+}
+
+fn render_nodes(nodes: &[Node], song: Option<&Song>, platform: &str, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Field(field) => output.push_str(&field_value(field, song, platform)),
+            Node::Condition {
+                field,
+                negate,
+                children,
+            } => {
+                if field_value(field, song, platform).is_empty() == *negate {
+                    render_nodes(children, song, platform, output);
+                }
+            }
+        }
+    }
+}
+
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, (secs / 60) % 60, secs % 60),
+    }
+}
+
+fn is_kind(song: &Song, kind: SongKind) -> String {
+    match song.kind == kind {
+        true => "true".to_string(),
+        false => String::new(),
+    }
+}
+
+fn field_value(field: &str, song: Option<&Song>, platform: &str) -> String {
+    if field == "platform" {
+        return platform.to_string();
+    }
+    let song = match song {
+        Some(song) => song,
+        None => return String::new(),
+    };
+
+    match field {
+        "title" => song.title.clone(),
+        // Ads and episodes don't have any artist
+        "artist" if song.kind == SongKind::Track => song.artist(),
+        "album" => song.album.clone(),
+        "progress" => song.progress_ms.map(format_duration).unwrap_or_default(),
+        "duration" => song.duration_ms.map(format_duration).unwrap_or_default(),
+        "url" => song.url.clone().unwrap_or_default(),
+        "artwork" => song.artwork_url.clone().unwrap_or_default(),
+        "id" => song.id.clone().unwrap_or_default(),
+        "show" => song.show.clone().unwrap_or_default(),
+        "publisher" => song.publisher.clone().unwrap_or_default(),
+        "track" => is_kind(song, SongKind::Track),
+        "episode" => is_kind(song, SongKind::Episode),
+        "ad" => is_kind(song, SongKind::Ad),
+        "unknown" => is_kind(song, SongKind::Unknown),
+        "playing" => match song.playing {
+            true => "true".to_string(),
+            false => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+pub fn from_env(var: &str, default: &str) -> Template {
+    let template = env::var(var).unwrap_or(default.to_string());
+    match Template::parse(template.as_str()) {
+        Ok(template) => template,
+        Err(err) => {
+            log::error!("Invalid {} template: {}", var, err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Templates used depending on the playback state.
+pub struct Templates {
+    pub playing: Template,
+    pub paused: Template,
+    pub idle: Template,
+}
+
+impl Templates {
+    pub fn from_env() -> Self {
+        Templates {
+            playing: from_env(PLAYING_ENV, DEFAULT_SONG),
+            paused: from_env(PAUSED_ENV, DEFAULT_SONG),
+            idle: from_env(IDLE_ENV, DEFAULT_IDLE),
+        }
+    }
+
+    /// Same as `from_env` with single line defaults.
+    pub fn bar_from_env() -> Self {
+        Templates {
+            playing: from_env(PLAYING_ENV, DEFAULT_BAR_SONG),
+            paused: from_env(PAUSED_ENV, DEFAULT_BAR_SONG),
+            idle: from_env(IDLE_ENV, DEFAULT_BAR_IDLE),
+        }
+    }
+
+    pub fn tooltip_from_env() -> Template {
+        from_env(TOOLTIP_ENV, DEFAULT_TOOLTIP)
+    }
+
+    pub fn render(&self, song: Option<&Song>, platform: &str) -> String {
+        match song {
+            Some(s) if s.playing => self.playing.render(song, platform),
+            Some(_) => self.paused.render(song, platform),
+            None => self.idle.render(song, platform),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> Song {
+        Song {
+            kind: SongKind::Track,
+            playing: true,
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string()],
+            album: "Album".to_string(),
+            duration_ms: Some(185_000),
+            progress_ms: Some(61_000),
+            ..Default::default()
+        }
+    }
+
+    fn render(template: &str, song: Option<&Song>) -> String {
+        Template::parse(template).unwrap().render(song, "Test")
+    }
+
+    #[test]
+    fn replaces_fields() {
+        let song = track();
+        assert_eq!(
+            render(
+                "{artist} - {title} ({progress}/{duration}) on {platform}",
+                Some(&song)
+            ),
+            "Artist - Title (1:01/3:05) on Test"
+        );
+        assert_eq!(render("{title}", None), "");
+    }
+
+    #[test]
+    fn escapes() {
+        let song = track();
+        assert_eq!(render("{{{title}}}", Some(&song)), "{Title}");
+        assert_eq!(render("a\\nb", None), "a\nb");
+    }
+
+    #[test]
+    fn conditionals() {
+        let mut song = track();
+        let template = "{title}{?album} [{album}]{/album}{!album} [single]{/album}";
+        assert_eq!(render(template, Some(&song)), "Title [Album]");
+        song.album.clear();
+        assert_eq!(render(template, Some(&song)), "Title [single]");
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let mut song = track();
+        let template =
+            "{?playing}>{?progress} {progress}{!duration}?{/duration}{/progress}{/playing}";
+        assert_eq!(render(template, Some(&song)), "> 1:01");
+        song.duration_ms = None;
+        assert_eq!(render(template, Some(&song)), "> 1:01?");
+        song.playing = false;
+        assert_eq!(render(template, Some(&song)), "");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let err = |template: &str| Template::parse(template).unwrap_err();
+        assert_eq!(err("{?album}{title}{/artist}"), "Unexpected `{/artist}`");
+        assert_eq!(err("{title}{/title}"), "Unexpected `{/title}`");
+        assert_eq!(err("{?album}{album}"), "Missing `{/album}`");
+        assert_eq!(err("{title"), "Unclosed `{title`");
+        assert_eq!(err("{nope}"), "Unknown field `nope`");
+        assert_eq!(err("{?nope}{/nope}"), "Unknown field `nope`");
+        assert_eq!(err("title}"), "Unexpected `}`, use `}}` to escape it");
+    }
+
+    #[test]
+    fn defaults_match_the_previous_output() {
+        let song = track();
+        let default = Template::parse(DEFAULT_SONG).unwrap();
+        assert_eq!(
+            default.render(Some(&song), "Test"),
+            format!("{} - {}\nAlbum: {}", song.title, song.artist(), song.album)
+        );
+
+        let episode = Song {
+            kind: SongKind::Episode,
+            title: "Episode".to_string(),
+            show: Some("Show".to_string()),
+            publisher: Some("Publisher".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            default.render(Some(&episode), "Test"),
+            "Episode - Show\nPublisher: Publisher"
+        );
+
+        let ad = Song {
+            kind: SongKind::Ad,
+            ..Default::default()
+        };
+        assert_eq!(default.render(Some(&ad), "Test"), "Advertisement");
+        assert_eq!(render(DEFAULT_IDLE, None), "No song detected");
+        assert_eq!(render(DEFAULT_BAR_SONG, Some(&song)), "Artist - Title");
+    }
+}
@@ -13,3 +13,25 @@ pub fn check_env_existence(var: &str, exit: bool) -> bool {
         }
     }
 }
+
+/// Address to listen on, from `ip_env` and `port_env` when they're set.
+pub fn get_server_env(
+    ip_env: &str,
+    default_ip: &str,
+    port_env: &str,
+    default_port: u16,
+) -> (String, u16) {
+    let ip = match check_env_existence(ip_env, false) {
+        true => env::var(ip_env).unwrap(),
+        false => default_ip.to_string(),
+    };
+    let port = match env::var(port_env).map(|port| port.parse()) {
+        Ok(Ok(port)) => port,
+        Ok(Err(_)) => {
+            log::error!("{} must be a port number", port_env);
+            process::exit(1);
+        }
+        Err(_) => default_port,
+    };
+    (ip, port)
+}